@@ -1,9 +1,42 @@
-use std::{collections::HashMap, error::Error, fs, time::Instant};
+use std::{collections::HashMap, error::Error, fs, path::{Path, PathBuf}, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+mod analysis;
+mod diff;
+mod drums;
+mod error;
+mod hash;
+mod hopo;
+mod ini;
+mod lint;
+mod lyrics;
+pub mod midi;
+mod reduce;
+mod section;
+mod sng;
+mod solo;
+mod tempo;
+mod writer;
+
+pub use analysis::ChartStats;
+pub use diff::{diff, Change, ChangeKind, ChangeTarget, SongDiff};
+pub use drums::{DrumFill, DrumNote};
+pub use error::ChartError;
+pub use hopo::HopoSettings;
+pub use ini::{SongIni, SongInfo};
+pub use lint::{LintKind, LintWarning};
+pub use lyrics::{LyricPhrase, Syllable};
+pub use section::Section;
+pub use sng::{SngEntry, SngFile, SngReader};
+pub use solo::Solo;
+pub use tempo::{Beat, Tempo, TempoMap, TimeSignature};
+pub use writer::write;
 
 // TODO: in the far future this should be rewritten as a serde serializer/deserializer
 //       this would allow for super easy chart editing and saving and honestly it would just be cool
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct SongSection {
     pub name: Option<String>,
     pub artist: Option<String>,
@@ -13,26 +46,32 @@ pub struct SongSection {
     pub charter: Option<String>,
     pub resolution: Option<usize>,
     pub difficulty: Option<usize>,
-    pub length: Option<f32>,
-    pub offset: Option<f32>,
-    pub preview_start: Option<f32>,
-    pub preview_end: Option<f32>,
+    pub length: Option<f64>,
+    pub offset: Option<f64>,
+    pub preview_start: Option<f64>,
+    pub preview_end: Option<f64>,
+
+    // keys we don't know about, `(key, value)` with the value exactly as it was in the file
+    pub unknown: Vec<(String, String)>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct TempoEvent {
-    pub bpm: f32,
+    // the file stores millibeats, so this is exact to 3 decimals
+    pub bpm: f64,
 
     pub time: f64, // in seconds
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum SyncEvent {
     TimeSignature { num: usize, den: usize },
     Tempo(TempoEvent),
+    // moonscraper pins the tick to this time (in microseconds) when editing, it doesn't change any timing when playing
+    Anchor { micros: u64 },
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum GlobalEvent {
     Section(String),
     PhraseStart,
@@ -41,16 +80,68 @@ pub enum GlobalEvent {
     SongEnd,
 }
 
-#[derive(Debug, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum Instrument {
     Single,
     DoubleGuitar,
     DoubleBass,
     DoubleRhythm,
+    Drums,
+    GhlGuitar,
+    GhlBass,
+    GhlRhythm,
+    GhlCoop,
+    Keys,
     Unknown,
 }
 
-#[derive(Debug, Hash, PartialEq, Eq)]
+impl Instrument {
+    /// The name of the instrument in `.chart` section names, `None` if it can't be written
+    pub fn chart_name(&self) -> Option<&'static str> {
+        match self {
+            Instrument::Single       => Some("Single"),
+            Instrument::DoubleGuitar => Some("DoubleGuitar"),
+            Instrument::DoubleBass   => Some("DoubleBass"),
+            Instrument::DoubleRhythm => Some("DoubleRhythm"),
+            Instrument::Drums        => Some("Drums"),
+            Instrument::GhlGuitar    => Some("GHLGuitar"),
+            Instrument::GhlBass      => Some("GHLBass"),
+            Instrument::GhlRhythm    => Some("GHLRhythm"),
+            Instrument::GhlCoop      => Some("GHLCoop"),
+            Instrument::Keys         => Some("Keys"),
+            Instrument::Unknown      => None,
+        }
+    }
+
+    /// Guitar Hero Live instruments use 6 frets (3 white and 3 black) instead of 5
+    pub fn is_ghl(&self) -> bool {
+        matches!(self, Instrument::GhlGuitar | Instrument::GhlBass | Instrument::GhlRhythm | Instrument::GhlCoop)
+    }
+
+    /// The song.ini key with this instrument's difficulty
+    pub fn ini_difficulty_key(&self) -> Option<&'static str> {
+        match self {
+            Instrument::Single       => Some("diff_guitar"),
+            Instrument::DoubleGuitar => Some("diff_guitar_coop"),
+            Instrument::DoubleBass   => Some("diff_bass"),
+            Instrument::DoubleRhythm => Some("diff_rhythm"),
+            Instrument::Drums        => Some("diff_drums"),
+            Instrument::GhlGuitar    => Some("diff_guitarghl"),
+            Instrument::GhlBass      => Some("diff_bassghl"),
+            Instrument::GhlRhythm    => Some("diff_rhythm_ghl"),
+            Instrument::GhlCoop      => Some("diff_guitar_coop_ghl"),
+            Instrument::Keys         => Some("diff_keys"),
+            Instrument::Unknown      => None,
+        }
+    }
+
+    /// Instruments without a strum bar, every note on these can be tapped
+    pub fn is_strumless(&self) -> bool {
+        matches!(self, Instrument::Keys)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum Difficulty {
     Easy,
     Medium,
@@ -58,39 +149,157 @@ pub enum Difficulty {
     Expert,
 }
 
+impl Difficulty {
+    /// The name of the difficulty in `.chart` section names
+    pub fn chart_name(&self) -> &'static str {
+        match self {
+            Difficulty::Easy   => "Easy",
+            Difficulty::Medium => "Medium",
+            Difficulty::Hard   => "Hard",
+            Difficulty::Expert => "Expert",
+        }
+    }
+}
+
 // currently only starpower is supported, but more may be in the future
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct StarpowerEvent {
     pub tick: usize,
     pub length: usize,
+
+    // in seconds, calculated after parsing
+    pub time: f64,
+    pub end_time: f64,
+}
+
+impl StarpowerEvent {
+    pub fn new(tick: usize, length: usize) -> Self {
+        StarpowerEvent { tick, length, time: 0.0, end_time: 0.0 }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum LocalEvent {
     SoloStart,
     SoloEnd,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Note {
     pub tick: usize,
-    pub frets: u8,
-    // frets without any flags
+    // bits are the `N` values from the chart, so bit 8 is the 6th ghl fret
+    pub frets: u16,
+    // frets without any flags, for ghl this is white 1-3 then black 1-3
     pub frets_masked: u8,
-    pub length: [usize; 8],
+    pub length: [usize; 9],
+    // when each fret's sustain ends in seconds, this is the same as `time` for frets without a sustain
+    pub sustain_end: [f64; 9],
 
     // TODO: possibly consolidate into one "flags" variable if we ever have more flags
     pub is_hopo: bool,
     pub is_chord: bool,
 
+    // index into `Chart::starpower_events` of the phrase this note is in
+    pub starpower: Option<usize>,
+    pub is_starpower_end: bool,
+
     pub time: f64, // in seconds
 }
 
-#[derive(Debug)]
+impl Note {
+    pub fn new(tick: usize, frets: u16, length: [usize; 9]) -> Self {
+        // the 6th ghl fret comes after the flags, so move it next to the others
+        let frets_masked = (frets & 0b00011111 | (frets >> 8 & 1) << 5) as u8;
+        // open notes count towards chords, but aren't part of the fret mask
+        let with_open = frets_masked | ((frets >> 7 & 1) << 6) as u8;
+        Note {
+            tick,
+            frets,
+            frets_masked,
+            length,
+            sustain_end: [0.0; 9],
+
+            is_chord: with_open.count_ones() > 1,
+
+            // calculated later
+            is_hopo: false,
+            starpower: None,
+            is_starpower_end: false,
+
+            time: 0.0
+        }
+    }
+
+    pub fn is_sustain(&self) -> bool { self.length.iter().any(|length| *length > 0) }
+    pub fn is_tap(&self) -> bool { self.frets >> 6 & 1 == 1 }
+    pub fn is_open(&self) -> bool { self.frets >> 7 & 1 == 1 }
+
+    /// `frets_masked` with the open note as bit 6
+    pub fn lanes(&self) -> u8 { self.frets_masked | (self.is_open() as u8) << 6 }
+
+    /// When the longest sustain in the note ends
+    pub fn end_time(&self) -> f64 { self.sustain_end.iter().copied().fold(self.time, f64::max) }
+
+    /// The white frets of a ghl note, as a bitmask of frets 1-3
+    pub fn ghl_white(&self) -> u8 { self.frets_masked & 0b111 }
+    /// The black frets of a ghl note, as a bitmask of frets 1-3
+    pub fn ghl_black(&self) -> u8 { self.frets_masked >> 3 & 0b111 }
+    /// The columns of a ghl note where both the white and black fret are held
+    pub fn ghl_barre(&self) -> u8 { self.ghl_white() & self.ghl_black() }
+}
+
+#[derive(Debug, Default)]
 pub struct Chart {
     pub notes: Vec<Note>,
     pub starpower_events: Vec<StarpowerEvent>,
-    pub local_events: Vec<LocalEvent>,
+    // `(tick, time, event)`, the time is calculated after parsing
+    pub local_events: Vec<(usize, f64, LocalEvent)>,
+    pub solos: Vec<Solo>,
+
+    // only used by drum charts, `notes` is empty for those
+    pub drum_notes: Vec<DrumNote>,
+    pub drum_fills: Vec<DrumFill>,
+
+    // lines we don't understand, `(tick, everything after the =)` in the order they were in the file
+    pub unknown_events: Vec<(usize, String)>,
+
+    // made from the Expert chart because the song didn't have this difficulty
+    pub is_generated: bool,
+}
+
+/// A section of a `.chart` file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionId {
+    Song,
+    SyncTrack,
+    Events,
+    Chart(Instrument, Difficulty),
+    // index into `Song::unknown_sections`
+    Unknown(usize),
+}
+
+/// The text of a `.chart` file exactly as it was, so `write` can give back the same file when nothing changed
+#[derive(Debug, Clone, Default)]
+pub struct ChartLayout {
+    pub bom: bool,
+    // lines end in `\r\n` instead of `\n`, the `\r` is still on every raw line
+    pub crlf: bool,
+    // anything before the first section
+    pub prefix: Vec<String>,
+    pub sections: Vec<RawSection>,
+}
+
+/// The raw lines of one section, split up so the writer can swap out just the ones that changed
+#[derive(Debug, Clone)]
+pub struct RawSection {
+    pub id: SectionId,
+    // without the brackets
+    pub name: String,
+    // the `[name]` and `{` lines
+    pub header: Vec<String>,
+    pub lines: Vec<String>,
+    // the `}` line and anything before the next section
+    pub footer: Vec<String>,
 }
 
 #[derive(Debug, Default)]
@@ -99,309 +308,657 @@ pub struct Song {
     pub sync_track: Option<Vec<(usize, SyncEvent)>>,
     pub events: Option<Vec<(usize, GlobalEvent)>>,
 
-    pub charts: HashMap<(Instrument, Difficulty), Chart>
+    // lines from `[SyncTrack]` and `[Events]` we don't understand, same as `Chart::unknown_events`
+    pub unknown_sync: Vec<(usize, String)>,
+    pub unknown_events: Vec<(usize, String)>,
+    // whole sections we don't understand, `(name, lines)`
+    pub unknown_sections: Vec<(String, Vec<String>)>,
+    // the original text of every section, `None` for midi
+    pub layout: Option<ChartLayout>,
+    // problems found while loading (skipped lines, midi tracks we can't use), `lint` reports them with everything else
+    pub load_warnings: Vec<LintWarning>,
+
+    pub charts: HashMap<(Instrument, Difficulty), Chart>,
+
+    // built from `sync_track` after parsing
+    pub tempo_map: Option<TempoMap>,
+    // built from the lyric events in `events`
+    pub lyrics: Vec<LyricPhrase>,
+
+    pub ini: Option<SongIni>,
+
+    // of the raw chart file, use `track_hash` to tell apart edits to a single track
+    pub hash: u64,
 }
 
-pub fn parse(file: String) -> Result<Song, Box<dyn Error>> {
-    let start = Instant::now();
+impl Song {
+    /// Moves every note and tempo event `seconds` later
+    pub fn delay(&mut self, seconds: f64) {
+        if let Some(tempo_map) = &mut self.tempo_map {
+            tempo_map.delay(seconds);
+        }
+        for (_, event) in self.sync_track.iter_mut().flatten() {
+            if let SyncEvent::Tempo(tempo) = event {
+                tempo.time += seconds;
+            }
+        }
+        for chart in self.charts.values_mut() {
+            for note in &mut chart.notes {
+                note.time += seconds;
+                for end in &mut note.sustain_end {
+                    *end += seconds;
+                }
+            }
+            for note in &mut chart.drum_notes {
+                note.time += seconds;
+            }
+            for phrase in &mut chart.starpower_events {
+                phrase.time += seconds;
+                phrase.end_time += seconds;
+            }
+            for (_, time, _) in &mut chart.local_events {
+                *time += seconds;
+            }
+            for solo in &mut chart.solos {
+                solo.time += seconds;
+                solo.end_time += seconds;
+            }
+        }
+        for phrase in &mut self.lyrics {
+            phrase.time += seconds;
+            phrase.end_time += seconds;
+            for syllable in &mut phrase.syllables {
+                syllable.time += seconds;
+            }
+        }
+    }
 
-    let file = String::from_utf8(fs::read(file)?)?;
-    let file: String = file.trim_start_matches("\u{FEFF}").into(); // strip BOM
+    /// Removes sustains shorter than `threshold` ticks, like song.ini's `sustain_cutoff_threshold`
+    pub fn trim_sustains(&mut self, threshold: usize) {
+        for chart in self.charts.values_mut() {
+            for note in &mut chart.notes {
+                for fret in 0..9 {
+                    if note.length[fret] < threshold {
+                        note.length[fret] = 0;
+                        note.sustain_end[fret] = note.time;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Loads the chart from a song folder or `.sng` file, preferring `notes.chart` over `notes.mid`
+pub fn load(folder: &str) -> Result<Song, Box<dyn Error>> {
+    if Path::new(folder).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("sng")) {
+        return load_sng(folder);
+    }
+
+    // the ini is needed while parsing for the HOPO settings
+    let ini = find_file(folder, "song.ini").map(|file| SongIni::load(&file.to_string_lossy())).transpose()?;
+    let hopo = ini.as_ref().map(HopoSettings::from_ini).unwrap_or_default();
+
+    let mut song = if let Some(chart) = find_file(folder, "notes.chart") {
+        let data = fs::read(&chart)?;
+        let (song, warnings) = parse_bytes(chart.to_string_lossy().into_owned(), data, true, hopo)?;
+        Song { load_warnings: warnings.iter().map(LintWarning::skipped_line).collect(), ..song }
+    } else if let Some(mid) = find_file(folder, "notes.mid") {
+        midi::parse_bytes(&mid.to_string_lossy(), &fs::read(&mid)?, hopo)?
+    } else {
+        return Err(format!("{folder} doesn't have a notes.chart or notes.mid").into());
+    };
+
+    if let Some(ini) = ini {
+        apply_ini(&mut song, ini);
+    }
+
+    Ok(song)
+}
+
+/// Finds a file in a folder ignoring case, the same way the library scanner does
+fn find_file(folder: &str, name: &str) -> Option<PathBuf> {
+    fs::read_dir(folder).ok()?.flatten()
+        .find(|entry| entry.file_name().to_str().is_some_and(|file| file.eq_ignore_ascii_case(name)))
+        .map(|entry| entry.path())
+}
 
-    let mut song = Song::default();
+/// Loads the chart straight out of a `.sng`, its metadata is used as the song.ini
+pub fn load_sng(file: &str) -> Result<Song, Box<dyn Error>> {
+    let sng = SngFile::open(file)?;
+    let ini = sng.ini();
+    let hopo = HopoSettings::from_ini(&ini);
+
+    let mut song = if sng.contains("notes.chart") {
+        let (song, warnings) = parse_bytes(format!("{file}/notes.chart"), sng.read("notes.chart")?, true, hopo)?;
+        Song { load_warnings: warnings.iter().map(LintWarning::skipped_line).collect(), ..song }
+    } else {
+        midi::parse_bytes(&format!("{file}/notes.mid"), &sng.read("notes.mid")?, hopo)?
+    };
+
+    apply_ini(&mut song, ini);
+    Ok(song)
+}
+
+fn apply_ini(song: &mut Song, ini: SongIni) {
+    // song.ini's delay is on top of the chart's offset
+    if let Some(delay) = ini.delay() {
+        song.delay(delay);
+    }
+    if let Some(threshold) = ini.get_parsed("sustain_cutoff_threshold") {
+        song.trim_sustains(threshold);
+    }
+
+    song.ini = Some(ini);
+}
+
+/// Parses a `.chart` file, failing on the first malformed line
+pub fn parse(file: String) -> Result<Song, ChartError> {
+    parse_with(file, false).map(|(song, _)| song)
+}
+
+/// Parses a `.chart` file, skipping malformed lines and returning them as warnings instead
+pub fn parse_lenient(file: String) -> Result<(Song, Vec<ChartError>), ChartError> {
+    parse_with(file, true)
+}
+
+fn parse_with(file: String, lenient: bool) -> Result<(Song, Vec<ChartError>), ChartError> {
+    let data = fs::read(&file)?;
+    parse_bytes(file, data, lenient, HopoSettings::default())
+}
+
+/// Parses `.chart` data that's already in memory, `file` is only used for errors
+pub fn parse_bytes(file: String, data: Vec<u8>, lenient: bool, hopo: HopoSettings) -> Result<(Song, Vec<ChartError>), ChartError> {
+    let hash = hash::hash_bytes(&data);
+    let text = String::from_utf8(data)?;
+    let bom = text.starts_with('\u{FEFF}');
+    let text = text.trim_start_matches("\u{FEFF}"); // strip BOM
+
+    let mut song = Song { hash, ..Default::default() };
+
+    let lines: Vec<String> = text.split("\n").map(|s| s.to_string()).collect();
+    let mut layout = ChartLayout { bom, crlf: lines[0].ends_with('\r'), ..Default::default() };
+    // where the text after the last section's `}` starts
+    let mut footer_start = None;
+    let mut ctx = Context {
+        file: &file,
+        lines: &lines,
+        section: String::new(),
+        lenient,
+        warnings: Vec::new(),
+    };
 
-    let lines: Vec<String> = file.split("\n").map(|s| s.to_string()).collect();
     let mut i = 0;
     while i < lines.len() {
         let line = lines[i].trim();
         if line.starts_with("[") {
+            match (footer_start, layout.sections.last_mut()) {
+                (Some(start), Some(last)) => last.footer = lines[start..i].to_vec(),
+                _ => layout.prefix = lines[..i].to_vec(),
+            }
+            let header_start = i;
+
             i += 1;
-            if lines[i].trim() == "{" { i += 1; }
+            if lines.get(i).is_some_and(|l| l.trim() == "{") { i += 1; }
+            let body_start = i;
 
             // look i know the function is called `remove_quotes` but just trust me
-            let section_type = remove_quotes(line.into()).to_lowercase();
-            match section_type.as_str() {
-                "song"      => song.metadata = Some(parse_song(&lines, &mut i)),
-                "synctrack" => song.sync_track = Some(parse_sync(&lines, &mut i)),
-                "events"    => song.events = Some(parse_events(&lines, &mut i)),
-                _ => {
-                    if ["expert", "hard", "medium", "easy"].iter().any(|s| section_type.starts_with(s)) {
-                        let chart = parse_chart(&lines, &mut i, section_type);
-                        if song.charts.insert((chart.0, chart.1), chart.2).is_some() {
-                            println!("chart contains duplicate note data for `{line}`!");
-                        }
-                    } else {
-                        println!("unhandled section type `{line}`")
-                    }
+            ctx.section = remove_quotes(line.into());
+            let section_type = ctx.section.to_lowercase();
+            let id = match section_type.as_str() {
+                "song" => {
+                    song.metadata = Some(parse_song(&mut ctx, &mut i)?);
+                    SectionId::Song
                 }
-            }
+                "synctrack" => {
+                    song.sync_track = Some(parse_sync(&mut ctx, &mut i, &mut song.unknown_sync)?);
+                    SectionId::SyncTrack
+                }
+                "events" => {
+                    song.events = Some(parse_events(&mut ctx, &mut i, &mut song.unknown_events)?);
+                    SectionId::Events
+                }
+                _ => if let Some(key) = chart_key(&section_type) {
+                    // duplicates are left for `Song::lint` to report
+                    song.charts.insert(key, parse_chart(&mut ctx, &mut i, key.0)?);
+                    SectionId::Chart(key.0, key.1)
+                } else {
+                    let name = ctx.section.clone();
+                    song.unknown_sections.push((name, parse_unknown(&mut ctx, &mut i)?));
+                    SectionId::Unknown(song.unknown_sections.len() - 1)
+                }
+            };
+
+            // `i` is on the `}`, or right before the next section if it's missing
+            let body_end = if lines.get(i).is_some_and(|l| l.trim() == "}") { i } else { (i + 1).min(lines.len()) };
+            layout.sections.push(RawSection {
+                id,
+                name: ctx.section.clone(),
+                header: lines[header_start..body_start].to_vec(),
+                lines: lines[body_start..body_end].to_vec(),
+                footer: Vec::new(),
+            });
+            footer_start = Some(body_end);
         }
         i += 1;
     }
+    match (footer_start, layout.sections.last_mut()) {
+        (Some(start), Some(last)) => last.footer = lines[start..].to_vec(),
+        _ => layout.prefix = lines.clone(),
+    }
+    song.layout = Some(layout);
 
-    // TODO: better error handling
-    let mut time = 0.0;
-    let mut last_tick = 0;
-    let mut last_bpm = 120.0;
-    let resolution = song.metadata.as_ref().expect("chart had no `Song` metadata!").resolution.expect("chart had no resolution!");
-    let mut bpm_events = Vec::new(); // this is just to make it 0.1% faster
-    for event in song.sync_track.as_mut().expect("chart had no `SyncTrack`!") {
-        let tick = event.0;
-        if let SyncEvent::Tempo(tempo) = &mut event.1 {
-            time += ticks_to_seconds(tick - last_tick, last_bpm, resolution);
-            tempo.time = time;
-            last_tick = tick;
-            last_bpm = tempo.bpm;
-            bpm_events.push((tick, time, tempo.bpm));
+    postprocess(&mut song, &file, hopo)?;
+    generate_difficulties(&mut song, hopo);
+
+    Ok((song, ctx.warnings))
+}
+
+/// Calculates note times and natural HOPOs for every chart in the song
+fn postprocess(song: &mut Song, file: &str, hopo: HopoSettings) -> Result<(), ChartError> {
+    let resolution = song.metadata.as_ref()
+        .ok_or_else(|| ChartError::MissingSection { file: file.into(), section: "Song" })?
+        .resolution
+        .filter(|resolution| *resolution > 0)
+        .ok_or_else(|| ChartError::MissingResolution { file: file.into() })?;
+    let sync_track = song.sync_track.as_mut().ok_or_else(|| ChartError::MissingSection { file: file.into(), section: "SyncTrack" })?;
+
+    if !sync_track.iter().any(|event| matches!(event.1, SyncEvent::Tempo(_))) {
+        return Err(ChartError::NoTempo { file: file.into() });
+    }
+
+    let offset = song.metadata.as_ref().and_then(|metadata| metadata.offset).unwrap_or(0.0);
+    let tempo_map = TempoMap::new(sync_track, resolution, offset);
+    for (tick, event) in sync_track {
+        if let SyncEvent::Tempo(tempo) = event {
+            tempo.time = tempo_map.tick_to_seconds(*tick);
         }
     }
 
-    for chart in song.charts.values_mut() {
-        postprocess_notes(chart, &bpm_events, resolution);
+    let hopo_threshold = hopo.threshold(resolution);
+    for ((_, difficulty), chart) in &mut song.charts {
+        postprocess_chart(chart, *difficulty, &tempo_map, hopo_threshold);
+    }
+
+    if let Some(events) = &song.events {
+        song.lyrics = lyrics::build_phrases(events, &tempo_map);
     }
 
-    println!("chart parsing took {}ms", start.elapsed().as_millis());
+    song.tempo_map = Some(tempo_map);
 
-    Ok(song)
+    Ok(())
 }
 
-fn postprocess_notes(chart: &mut Chart, bpm_events: &[(usize, f64, f32)], resolution: usize) {
-    let mut last_bpm = 0;
-    let mut i = 0;
-    while i < chart.notes.len() {
-        let split = chart.notes.split_at_mut(i);
-        let note = &mut split.1[0];
+fn postprocess_chart(chart: &mut Chart, difficulty: Difficulty, tempo_map: &TempoMap, hopo_threshold: usize) {
+    postprocess_notes(chart, tempo_map);
+    hopo::compute_hopos(chart, difficulty, hopo_threshold);
+    solo::resolve_solos(chart, tempo_map);
+
+    for note in &mut chart.drum_notes {
+        note.time = tempo_map.tick_to_seconds(note.tick);
+    }
+}
+
+/// Fills in missing difficulties from Expert, this has to happen once forcing won't change anymore
+fn generate_difficulties(song: &mut Song, hopo: HopoSettings) {
+    let Some(tempo_map) = &song.tempo_map else { return };
+    let hopo_threshold = hopo.threshold(tempo_map.resolution);
+    reduce::fill_missing_difficulties(&mut song.charts, tempo_map.resolution, hopo_threshold);
+
+    for ((_, difficulty), chart) in song.charts.iter_mut().filter(|(_, chart)| chart.is_generated) {
+        postprocess_chart(chart, *difficulty, tempo_map, hopo_threshold);
+    }
+}
 
-        while last_bpm + 1 < bpm_events.len() && bpm_events[last_bpm + 1].0 <= note.tick {
-            last_bpm += 1;
+fn postprocess_notes(chart: &mut Chart, tempo_map: &TempoMap) {
+    for note in &mut chart.notes {
+        note.time = tempo_map.tick_to_seconds(note.tick);
+        for fret in 0..9 {
+            // going through the tempo map again means sustains over bpm changes are still correct
+            note.sustain_end[fret] = tempo_map.tick_to_seconds(note.tick.saturating_add(note.length[fret]));
         }
+    }
 
-        let bpm = &bpm_events[last_bpm];
-        note.time = bpm.1 + ticks_to_seconds(note.tick - bpm.0, bpm.2, resolution);
+    for (index, phrase) in chart.starpower_events.iter_mut().enumerate() {
+        phrase.time = tempo_map.tick_to_seconds(phrase.tick);
+        phrase.end_time = tempo_map.tick_to_seconds(phrase.tick.saturating_add(phrase.length));
 
-        if i > 0 && !note.is_chord {
-            let last_note = &split.0[i - 1];
-            if last_note.is_chord || last_note.frets_masked != note.frets_masked
-            && note.tick - last_note.tick <= (65.0 * resolution as f32 / 192.0) as usize {
-                note.is_hopo = true;
-            }
+        // phrases with no length still get the note they start on
+        let start = chart.notes.partition_point(|note| note.tick < phrase.tick);
+        let end = chart.notes.partition_point(|note| note.tick < phrase.tick.saturating_add(phrase.length.max(1)));
+        for note in &mut chart.notes[start..end] {
+            note.starpower = Some(index);
+        }
+        if end > start {
+            chart.notes[end - 1].is_starpower_end = true;
         }
+    }
+}
 
-        // forcing
-        note.is_hopo ^= note.frets >> 5 & 1 == 1;
+/// Keeps track of where we are in the file for error messages
+struct Context<'a> {
+    file: &'a str,
+    lines: &'a [String],
+    section: String,
+    lenient: bool,
+    warnings: Vec<ChartError>,
+}
 
-        i += 1;
+impl Context<'_> {
+    /// Reports a malformed line, in lenient mode this is saved as a warning instead of failing
+    /// `offset` is the byte offset into the line of the offending text
+    fn error(&mut self, i: usize, offset: usize, reason: &'static str) -> Result<(), ChartError> {
+        let line = &self.lines[i];
+
+        let err = ChartError::Malformed {
+            file: self.file.into(),
+            line: i + 1,
+            column: offset + 1,
+            section: self.section.clone(),
+            text: line.trim().into(),
+            reason,
+        };
+
+        if !self.lenient {
+            return Err(err);
+        }
+        self.warnings.push(err);
+        Ok(())
     }
 }
 
-fn ticks_to_seconds(ticks: usize, bpm: f32, resolution: usize) -> f64 {
-    ticks as f64 / resolution as f64 * (60.0 / bpm as f64)
+/// A piece of a line and the byte offset it starts at, so errors can point at the right column
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    offset: usize,
 }
 
-fn parse_song(lines: &[String], i: &mut usize) -> SongSection {
-    let mut res = SongSection::default();
+impl<'a> Token<'a> {
+    /// Same as `str::split`, but every piece keeps track of where it starts
+    fn split(self, separator: &str) -> Vec<Token<'a>> {
+        let mut offset = self.offset;
+        self.text.split(separator).map(|text| {
+            let token = Token { text, offset };
+            offset += text.len() + separator.len();
+            token
+        }).collect()
+    }
 
+    /// Right after the last character, for pointing at something that's missing
+    fn end(&self) -> usize { self.offset + self.text.len() }
+}
+
+/// The byte offset of the offending text and the reason a line couldn't be parsed
+type LineResult<T> = Result<T, (usize, &'static str)>;
+
+/// Runs `f` on every line until the end of the current section
+fn parse_lines<'a>(ctx: &mut Context<'a>, i: &mut usize, f: impl FnMut(Token<'a>) -> LineResult<()>) -> Result<(), ChartError> {
+    let lines = section_lines(ctx, i)?;
+    run_lines(ctx, lines, f)
+}
+
+/// Same as `parse_lines`, but the lines are sorted by tick first since some charts have them out of order
+/// Sorting is stable, so lines on the same tick stay in the order they were in
+fn parse_tick_lines<'a>(ctx: &mut Context<'a>, i: &mut usize, f: impl FnMut(Token<'a>) -> LineResult<()>) -> Result<(), ChartError> {
+    // lines without a valid tick get reported by `f`, until then they stay right after the line before them
+    let mut last_tick = 0;
+    let mut lines: Vec<_> = section_lines(ctx, i)?.into_iter().map(|line| {
+        if let Some(Ok(tick)) = line.1.text.split(' ').next().map(str::parse::<usize>) {
+            last_tick = tick;
+        }
+        (last_tick, line)
+    }).collect();
+    lines.sort_by_key(|(tick, _)| *tick);
+
+    run_lines(ctx, lines.into_iter().map(|(_, line)| line).collect(), f)
+}
+
+/// Collects the non-empty lines until the end of the current section, along with their line index
+fn section_lines<'a>(ctx: &mut Context<'a>, i: &mut usize) -> Result<Vec<(usize, Token<'a>)>, ChartError> {
+    let lines = ctx.lines;
+    let mut res = Vec::new();
     loop {
-        let line = lines[*i].trim();
-        if line == "}" { break; }
-
-        let split: Vec<&str> = line.split(" = ").collect();
-        match split[0].to_lowercase().as_str() {
-            "name"         => res.name = Some(remove_quotes(split[1].into())),
-            "artist"       => res.artist = Some(remove_quotes(split[1].into())),
-            "album"        => res.album = Some(remove_quotes(split[1].into())),
-            "genre"        => res.genre = Some(remove_quotes(split[1].into())),
-            "year"         => res.year = Some(remove_quotes(split[1].into()).trim_start_matches(", ").into()),
-            "charter"      => res.charter = Some(remove_quotes(split[1].into())),
-            "resolution"   => res.resolution = Some(split[1].parse().unwrap()),
-            "difficulty"   => res.difficulty = Some(split[1].parse().unwrap()),
-            "length"       => res.length = Some(split[1].parse().unwrap()),
-            "offset"       => res.offset = Some(split[1].parse().unwrap()),
-            "previewstart" => res.preview_start = Some(split[1].parse().unwrap()),
-            "previewend"   => res.preview_end = Some(split[1].parse().unwrap()),
-            _ => println!("unknown song metadata `{}` with value `{}`", split[0], split[1]),
+        let Some(line) = lines.get(*i) else {
+            let last = lines.len() - 1;
+            ctx.error(last, lines[last].len(), "section never ends")?;
+            return Ok(res);
+        };
+
+        let token = Token { text: line.trim(), offset: line.len() - line.trim_start().len() };
+        if token.text == "}" { return Ok(res); }
+
+        // a missing `}`, back up so the next section still gets parsed
+        if token.text.starts_with("[") {
+            let err = ctx.error(*i, token.offset, "section never ends");
+            *i -= 1;
+            return err.map(|_| res);
+        }
+
+        if !token.text.is_empty() {
+            res.push((*i, token));
         }
 
         *i += 1;
     }
+}
 
-    res
+fn run_lines<'a>(ctx: &mut Context<'a>, lines: Vec<(usize, Token<'a>)>, mut f: impl FnMut(Token<'a>) -> LineResult<()>) -> Result<(), ChartError> {
+    for (i, line) in lines {
+        if let Err((offset, reason)) = f(line) {
+            ctx.error(i, offset, reason)?;
+        }
+    }
+    Ok(())
 }
 
-fn parse_sync(lines: &[String], i: &mut usize) -> Vec<(usize, SyncEvent)> {
-    let mut res = Vec::new();
+/// Gets the value at `index`, pointing at the end of the line if it's missing
+fn get<'a>(split: &[Token<'a>], index: usize, line: Token) -> LineResult<Token<'a>> {
+    split.get(index).copied().ok_or((line.end(), "missing value"))
+}
 
-    loop {
-        let line = lines[*i].trim();
-        if line == "}" { break; }
+fn number<T: FromStr>(token: Token) -> LineResult<T> {
+    token.text.parse().map_err(|_| (token.offset, "invalid number"))
+}
+
+fn parse_song(ctx: &mut Context, i: &mut usize) -> Result<SongSection, ChartError> {
+    let mut res = SongSection::default();
+
+    parse_lines(ctx, i, |line| {
+        let split = line.split(" = ");
+        let value = get(&split, 1, line)?;
+        match split[0].text.to_lowercase().as_str() {
+            "name"         => res.name = Some(remove_quotes(value.text.into())),
+            "artist"       => res.artist = Some(remove_quotes(value.text.into())),
+            "album"        => res.album = Some(remove_quotes(value.text.into())),
+            "genre"        => res.genre = Some(remove_quotes(value.text.into())),
+            "year"         => res.year = Some(remove_quotes(value.text.into()).trim_start_matches(", ").into()),
+            "charter"      => res.charter = Some(remove_quotes(value.text.into())),
+            "resolution"   => res.resolution = Some(number(value)?),
+            "difficulty"   => res.difficulty = Some(number(value)?),
+            "length"       => res.length = Some(number(value)?),
+            "offset"       => res.offset = Some(number(value)?),
+            "previewstart" => res.preview_start = Some(number(value)?),
+            "previewend"   => res.preview_end = Some(number(value)?),
+            _ => res.unknown.push((split[0].text.into(), raw_value(line))),
+        }
+        Ok(())
+    })?;
+
+    Ok(res)
+}
 
-        let split: Vec<&str> = line.split(" ").collect();
-        let tick = split[0].parse().unwrap();
-        match split[2].to_lowercase().as_str() {
+fn parse_sync(ctx: &mut Context, i: &mut usize, unknown: &mut Vec<(usize, String)>) -> Result<Vec<(usize, SyncEvent)>, ChartError> {
+    let mut res = Vec::new();
+
+    parse_tick_lines(ctx, i, |line| {
+        let split = line.split(" ");
+        let tick = number(split[0])?;
+        match get(&split, 2, line)?.text.to_lowercase().as_str() {
             "ts" => res.push((
                 tick,
                 SyncEvent::TimeSignature {
-                    num: split[3].parse().unwrap(),
+                    num: number(get(&split, 3, line)?)?,
                     den: if let Some(den) = split.get(4) {
-                        2usize.pow(den.parse().unwrap())
+                        2usize.checked_pow(number(*den)?).ok_or((den.offset, "time signature denominator is too large"))?
                     } else {
                         4
                     }
                 }
             )),
-            "b" => res.push((tick, SyncEvent::Tempo(TempoEvent { bpm: split[3].parse::<usize>().unwrap() as f32 / 1000.0, time: 0.0 }))),
-            _ => {}
+            "b" => {
+                let bpm = get(&split, 3, line)?;
+                let millibeats = number::<usize>(bpm)?;
+                if millibeats == 0 {
+                    return Err((bpm.offset, "tempo can't be 0"));
+                }
+                res.push((tick, SyncEvent::Tempo(TempoEvent { bpm: millibeats as f64 / 1000.0, time: 0.0 })));
+            }
+            "a" => res.push((tick, SyncEvent::Anchor { micros: number(get(&split, 3, line)?)? })),
+            _ => unknown.push((tick, raw_value(line))),
         }
+        Ok(())
+    })?;
 
-        *i += 1;
-    }
-
-    res
+    Ok(res)
 }
 
-fn parse_events(lines: &[String], i: &mut usize) -> Vec<(usize, GlobalEvent)> {
+fn parse_events(ctx: &mut Context, i: &mut usize, unknown: &mut Vec<(usize, String)>) -> Result<Vec<(usize, GlobalEvent)>, ChartError> {
     let mut res = Vec::new();
 
-    loop {
-        let line = lines[*i].trim();
-        if line == "}" { break; }
-
-        let split: Vec<&str> = line.split(" = ").collect();
-        let tick: usize = split[0].parse().unwrap();
+    parse_tick_lines(ctx, i, |line| {
+        let split = line.split(" = ");
+        let tick: usize = number(split[0])?;
 
         // variable naming be damned (i'm tired okay)
-        let val = remove_chars(split[1].into(), 3, 1);
-        let val: Vec<&str> = val.split([' ', '_']).collect();
-
-        let event_type = val[0].to_lowercase();
-        let val = val[1..].join(" ");
-
-        match event_type.as_str() {
-            "section"      => res.push((tick, GlobalEvent::Section(val))),
-            "phrase_start" => res.push((tick, GlobalEvent::PhraseStart)),
-            "lyric"        => res.push((tick, GlobalEvent::Lyric(val))),
-            "phrase_end"   => res.push((tick, GlobalEvent::PhraseEnd)),
-            "end"          => res.push((tick, GlobalEvent::SongEnd)),
-            _ => {}
+        let val = get(&split, 1, line)?.text.trim_start_matches('E').trim().trim_matches('"');
+
+        // the type is split off by the first space or underscore (`section_intro`, `phrase_start`), the rest is kept as is
+        // the original text only matters for writing, and that comes from `Song::layout`
+        let (event_type, val) = val.split_once([' ', '_']).unwrap_or((val, ""));
+        match (event_type.to_lowercase().as_str(), val) {
+            ("section", _) => res.push((tick, GlobalEvent::Section(val.into()))),
+            ("phrase", _) if val.eq_ignore_ascii_case("start") => res.push((tick, GlobalEvent::PhraseStart)),
+            ("lyric", _) => res.push((tick, GlobalEvent::Lyric(val.into()))),
+            ("phrase", _) if val.eq_ignore_ascii_case("end") => res.push((tick, GlobalEvent::PhraseEnd)),
+            ("end", _) => res.push((tick, GlobalEvent::SongEnd)),
+            _ => unknown.push((tick, raw_value(line))),
         }
+        Ok(())
+    })?;
 
-        *i += 1;
-    }
-
-    res
+    Ok(res)
 }
 
-fn parse_chart(lines: &[String], i: &mut usize, chart_type: String) -> (Instrument, Difficulty, Chart) {
-    let difficulty = if chart_type.starts_with("easy") {
-        Difficulty::Easy
-    } else if chart_type.starts_with("medium") {
-        Difficulty::Medium
-    } else if chart_type.starts_with("hard") {
-        Difficulty::Hard
-    } else {
-        Difficulty::Expert
+/// Works out the instrument and difficulty from a lowercase section name like `expertsingle`
+fn chart_key(section_type: &str) -> Option<(Instrument, Difficulty)> {
+    let (difficulty, instrument) = [Difficulty::Expert, Difficulty::Hard, Difficulty::Medium, Difficulty::Easy].into_iter()
+        .find_map(|difficulty| Some((difficulty, section_type.strip_prefix(&difficulty.chart_name().to_lowercase())?)))?;
+
+    let instrument = match instrument {
+        "single"       => Instrument::Single,
+        "doubleguitar" => Instrument::DoubleGuitar,
+        "doublebass"   => Instrument::DoubleBass,
+        "doublerhythm" => Instrument::DoubleRhythm,
+        "drums"        => Instrument::Drums,
+        "ghlguitar"    => Instrument::GhlGuitar,
+        "ghlbass"      => Instrument::GhlBass,
+        "ghlrhythm"    => Instrument::GhlRhythm,
+        "ghlcoop"      => Instrument::GhlCoop,
+        "keys"         => Instrument::Keys,
+        // instruments we don't know about get kept as unknown sections instead
+        _ => return None,
     };
+    Some((instrument, difficulty))
+}
 
-    let instrument = if chart_type.ends_with("single") {
-        Instrument::Single
-    } else if chart_type.ends_with("doubleguitar") {
-        Instrument::DoubleGuitar
-    } else if chart_type.ends_with("doublebass") {
-        Instrument::DoubleBass
-    } else if chart_type.ends_with("doublerhythm") {
-        Instrument::DoubleRhythm
-    } else {
-        Instrument::Unknown
-    };
+fn parse_chart(ctx: &mut Context, i: &mut usize, instrument: Instrument) -> Result<Chart, ChartError> {
+    if instrument == Instrument::Drums {
+        return drums::parse_drums(ctx, i);
+    }
 
     let mut last_tick = 0;
     let mut cur_frets = 0;
-    let mut cur_length = [0; 8];
+    let mut cur_length = [0; 9];
     let mut notes = Vec::new();
     let mut starpower_events = Vec::new();
     let mut local_events = Vec::new();
+    let mut unknown_events = Vec::new();
 
-    loop {
-        let line = lines[*i].trim();
-        if line == "}" { break; }
-
-        let split: Vec<&str> = line.split(" = ").collect();
-        let tick: usize = split[0].parse().unwrap();
+    parse_tick_lines(ctx, i, |line| {
+        let split = line.split(" = ");
+        let tick: usize = number(split[0])?;
 
         // variable naming be damned (i'm tired okay)
-        let val = split[1].to_string();
-        let val: Vec<&str> = val.split(' ').collect();
+        let val = get(&split, 1, line)?.split(" ");
 
-        let note_type = val[0].to_lowercase();
+        let note_type = val[0].text.to_lowercase();
 
         match note_type.as_str() {
             "n" => {
-                if last_tick != tick && cur_frets != 0 {
-                    let frets_masked = (cur_frets & 0b00011111) | ((cur_frets >> 7 & 1) << 5);
-                    notes.push(Note {
-                        tick: last_tick,
-                        frets: cur_frets,
-                        frets_masked: frets_masked & 0b00011111, // don't worry about it
-                        length: cur_length,
-
-                        is_chord: (frets_masked & (frets_masked - 1)) != 0,
-
-                        // calculated later
-                        is_hopo: false,
+                let fret_text = get(&val, 1, line)?;
+                let fret: u8 = number(fret_text)?;
+                // ghl puts its 6th fret on 8 since 5-7 are already taken
+                if fret > 8 || fret == 8 && !instrument.is_ghl() {
+                    return Err((fret_text.offset, "unknown note type"));
+                }
+                let length = number(get(&val, 2, line)?)?;
 
-                        time: 0.0
-                    });
+                if last_tick != tick && cur_frets != 0 {
+                    notes.push(Note::new(last_tick, cur_frets, cur_length));
 
                     cur_frets = 0;
-                    cur_length = [0; 8];
+                    cur_length = [0; 9];
                 }
 
-                let fret: u8 = val[1].parse().unwrap();
-                let length = val[2].parse().unwrap();
                 cur_frets |= 1 << fret;
                 cur_length[fret as usize] = length;
 
                 last_tick = tick;
             }
-            "s" => {
-                if val[1] == "2" {
-                    starpower_events.push(StarpowerEvent { tick, length: val[2].parse().unwrap() });
-                }
+            "s" if val.get(1).is_some_and(|kind| kind.text == "2") => {
+                starpower_events.push(StarpowerEvent::new(tick, number(get(&val, 2, line)?)?));
             }
             "e" => {
-                match val[1] {
-                    "solo" => local_events.push(LocalEvent::SoloStart),
-                    "soloend" => local_events.push(LocalEvent::SoloEnd),
-                    _ => {},
+                match get(&val, 1, line)?.text {
+                    "solo" => local_events.push((tick, 0.0, LocalEvent::SoloStart)),
+                    "soloend" => local_events.push((tick, 0.0, LocalEvent::SoloEnd)),
+                    _ => unknown_events.push((tick, raw_value(line))),
                 }
             }
-            _ => {}
+            _ => unknown_events.push((tick, raw_value(line))),
         }
-
-        *i += 1;
+        Ok(())
+    })?;
+    if cur_frets != 0 {
+        notes.push(Note::new(last_tick, cur_frets, cur_length));
     }
-    let frets_masked = (cur_frets & 0b00011111) | ((cur_frets >> 7 & 1) << 5);
-    notes.push(Note {
-        tick: last_tick,
-        frets: cur_frets,
-        frets_masked,
-        length: cur_length,
 
-        is_chord: (frets_masked & (frets_masked - 1)) != 0,
-
-        // calculated later
-        is_hopo: false,
-
-        time: 0.0
-    });
-
-    (instrument, difficulty, Chart {
+    Ok(Chart {
         notes,
         starpower_events,
         local_events,
+        unknown_events,
+        ..Default::default()
     })
 }
 
+/// Keeps the lines of a section we don't understand as they are
+fn parse_unknown(ctx: &mut Context, i: &mut usize) -> Result<Vec<String>, ChartError> {
+    let mut res = Vec::new();
+
+    parse_lines(ctx, i, |line| {
+        res.push(line.text.to_string());
+        Ok(())
+    })?;
+
+    Ok(res)
+}
+
+/// Everything after the ` = ` in a line, for keeping lines we don't understand
+fn raw_value(line: Token) -> String {
+    line.text.split_once(" = ").map_or(line.text, |(_, value)| value).into()
+}
+
 fn remove_quotes(s: String) -> String {
     remove_chars(s, 1, 1)
 }
@@ -409,5 +966,233 @@ fn remove_quotes(s: String) -> String {
 // RUSTTTTTT
 #[inline]
 fn remove_chars(s: String, start: usize, end: usize) -> String {
-    s.chars().skip(start).take(s.len() - start - end).collect()
+    let len = s.chars().count();
+    s.chars().skip(start).take(len.saturating_sub(start + end)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(text: &str, lenient: bool) -> Result<(Song, Vec<ChartError>), ChartError> {
+        parse_bytes("test.chart".into(), text.as_bytes().to_vec(), lenient, HopoSettings::default())
+    }
+
+    #[test]
+    fn out_of_order_lines_are_sorted() {
+        let (song, warnings) = parse_str("\
+[Song]
+{
+  Resolution = 192
+}
+[SyncTrack]
+{
+  192 = B 140000
+  0 = B 120000
+}
+[ExpertSingle]
+{
+  384 = N 1 0
+  0 = N 0 0
+  384 = N 2 0
+  192 = N 3 0
+}
+", false).unwrap();
+
+        assert!(warnings.is_empty());
+        let ticks: Vec<usize> = song.sync_track.unwrap().iter().map(|(tick, _)| *tick).collect();
+        assert_eq!(ticks, [0, 192]);
+
+        let notes = &song.charts[&(Instrument::Single, Difficulty::Expert)].notes;
+        let notes: Vec<(usize, u16)> = notes.iter().map(|note| (note.tick, note.frets)).collect();
+        assert_eq!(notes, [(0, 0b1), (192, 0b1000), (384, 0b110)]);
+    }
+
+    fn offset_chart(offset: &str) -> Song {
+        parse_str(&format!("\
+[Song]
+{{
+  Resolution = 192
+  Offset = {offset}
+}}
+[SyncTrack]
+{{
+  0 = B 120000
+  768 = B 240000
+}}
+[ExpertSingle]
+{{
+  0 = N 0 192
+  384 = N 1 0
+  384 = S 2 384
+  384 = E solo
+  768 = N 2 192
+  768 = E soloend
+  960 = N 3 0
+}}
+"), false).unwrap().0
+    }
+
+    /// Checks that every time in `song` is `shift` seconds after the same time in `base`
+    fn assert_shifted(base: &Song, song: &Song, shift: f64) {
+        let close = |a: f64, b: f64| assert!((a + shift - b).abs() < 1e-9, "{a} + {shift} != {b}");
+
+        for ((_, base_event), (_, event)) in base.sync_track.iter().flatten().zip(song.sync_track.iter().flatten()) {
+            if let (SyncEvent::Tempo(base_tempo), SyncEvent::Tempo(tempo)) = (base_event, event) {
+                close(base_tempo.time, tempo.time);
+            }
+        }
+
+        let (base_map, tempo_map) = (base.tempo_map.as_ref().unwrap(), song.tempo_map.as_ref().unwrap());
+        for (base_tempo, tempo) in base_map.tempos().iter().zip(tempo_map.tempos()) {
+            close(base_tempo.time, tempo.time);
+        }
+        for tick in [0, 100, 384, 768, 1000] {
+            close(base_map.tick_to_seconds(tick), tempo_map.tick_to_seconds(tick));
+        }
+
+        let key = (Instrument::Single, Difficulty::Expert);
+        let (base_chart, chart) = (&base.charts[&key], &song.charts[&key]);
+        for (base_note, note) in base_chart.notes.iter().zip(&chart.notes) {
+            close(base_note.time, note.time);
+            for fret in 0..9 {
+                close(base_note.sustain_end[fret], note.sustain_end[fret]);
+            }
+        }
+        for (base_phrase, phrase) in base_chart.starpower_events.iter().zip(&chart.starpower_events) {
+            close(base_phrase.time, phrase.time);
+            close(base_phrase.end_time, phrase.end_time);
+        }
+        for ((_, base_time, _), (_, time, _)) in base_chart.local_events.iter().zip(&chart.local_events) {
+            close(*base_time, *time);
+        }
+        assert_eq!(base_chart.solos.len(), chart.solos.len());
+        for (base_solo, solo) in base_chart.solos.iter().zip(&chart.solos) {
+            close(base_solo.time, solo.time);
+            close(base_solo.end_time, solo.end_time);
+        }
+    }
+
+    #[test]
+    fn chart_offset_shifts_everything() {
+        let base = offset_chart("0");
+        let song = offset_chart("1.5");
+
+        // 120bpm is half a second per beat, then 240bpm is a quarter second
+        let notes = &song.charts[&(Instrument::Single, Difficulty::Expert)].notes;
+        let times: Vec<f64> = notes.iter().map(|note| note.time).collect();
+        assert_eq!(times, [1.5, 2.5, 3.5, 3.75]);
+        assert_eq!(notes[2].sustain_end[2], 3.75);
+        assert_eq!(song.tempo_map.as_ref().unwrap().tick_to_seconds(768), 3.5);
+        assert_eq!(song.charts[&(Instrument::Single, Difficulty::Expert)].solos.len(), 1);
+
+        assert_shifted(&base, &song, 1.5);
+    }
+
+    #[test]
+    fn ini_delay_adds_to_chart_offset() {
+        let base = offset_chart("0");
+        let mut song = offset_chart("1.5");
+        apply_ini(&mut song, SongIni::parse("[song]\ndelay = 500\n"));
+
+        assert_shifted(&base, &song, 2.0);
+    }
+
+    #[test]
+    fn load_ignores_file_name_case() {
+        let folder = std::env::temp_dir().join(format!("open_hero_case_{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        fs::copy(concat!(env!("CARGO_MANIFEST_DIR"), "/songs/openchordtest/notes.chart"), folder.join("Notes.Chart")).unwrap();
+        fs::write(folder.join("SONG.INI"), "[song]\ndelay = 1000\n").unwrap();
+
+        let song = load(&folder.to_string_lossy());
+        fs::remove_dir_all(&folder).unwrap();
+
+        let song = song.unwrap();
+        assert!(song.ini.is_some());
+        assert!(!song.charts.is_empty());
+    }
+
+    #[test]
+    fn huge_sustains_dont_overflow() {
+        let (song, _) = parse_str(&format!("\
+[Song]
+{{
+  Resolution = 192
+}}
+[SyncTrack]
+{{
+  0 = B 120000
+}}
+[ExpertSingle]
+{{
+  192 = N 0 {}
+}}
+", usize::MAX), true).unwrap();
+
+        let note = &song.charts[&(Instrument::Single, Difficulty::Expert)].notes[0];
+        assert!(note.sustain_end[0] > note.time);
+    }
+
+    #[test]
+    fn huge_starpower_phrases_dont_overflow() {
+        let (song, _) = parse_str(&format!("\
+[Song]
+{{
+  Resolution = 192
+}}
+[SyncTrack]
+{{
+  0 = B 120000
+}}
+[Events]
+{{
+  0 = E \"section intro\"
+}}
+[ExpertSingle]
+{{
+  192 = N 0 {max}
+  192 = S 2 {max}
+  384 = N 1 0
+}}
+", max = usize::MAX), true).unwrap();
+
+        let chart = &song.charts[&(Instrument::Single, Difficulty::Expert)];
+        assert!(chart.notes.iter().all(|note| note.starpower == Some(0)));
+        assert!(chart.starpower_events[0].end_time > chart.starpower_events[0].time);
+
+        let section = &song.sections()[0];
+        assert_eq!(song.section_chart(Instrument::Single, Difficulty::Expert, section, false).unwrap().notes.len(), 2);
+        song.lint();
+    }
+
+    #[test]
+    fn malformed_lines_point_at_the_column() {
+        let text = "\
+[Song]
+{
+  Resolution = 192
+}
+[SyncTrack]
+{
+  0 = B 120000
+}
+[ExpertSingle]
+{
+  0 = N x 0
+  192 = N 1
+}
+";
+        let Err(ChartError::Malformed { line, column, reason, .. }) = parse_str(text, false) else { panic!("expected an error") };
+        assert_eq!((line, column, reason), (11, 9, "invalid number"));
+
+        let (song, warnings) = parse_str(text, true).unwrap();
+        let positions: Vec<(usize, usize)> = warnings.iter().map(|warning| match warning {
+            ChartError::Malformed { line, column, .. } => (*line, *column),
+            _ => panic!("unexpected warning {warning}"),
+        }).collect();
+        // the missing length points right after the end of the line
+        assert_eq!(positions, [(11, 9), (12, 12)]);
+        assert!(song.charts[&(Instrument::Single, Difficulty::Expert)].notes.is_empty());
+    }
 }
@@ -0,0 +1,130 @@
+use super::{Chart, Difficulty, GlobalEvent, Instrument, Song};
+
+/// Summary of how dense and hard a chart is
+#[derive(Debug, Clone, Default)]
+pub struct ChartStats {
+    pub note_count: usize,
+    pub chord_count: usize,
+    pub hopo_count: usize,
+    pub tap_count: usize,
+    pub open_count: usize,
+
+    // notes per second over the whole chart
+    pub average_nps: f64,
+    // the most notes in any 1 second window
+    pub peak_nps: f64,
+    // `(time, nps)` sampled every `NPS_STEP` seconds
+    pub nps: Vec<(f64, f64)>,
+    // `(section name, peak nps)` for each section that has notes
+    pub section_peaks: Vec<(String, f64)>,
+
+    // the most notes in a row with no more than a 16th note between them
+    pub longest_stream: usize,
+
+    // a rough estimate on the same 0-6 scale as song.ini's `diff_*` values
+    pub difficulty: f32,
+}
+
+impl ChartStats {
+    pub fn chord_ratio(&self) -> f32 { self.ratio(self.chord_count) }
+    pub fn hopo_ratio(&self) -> f32 { self.ratio(self.hopo_count) }
+    pub fn tap_ratio(&self) -> f32 { self.ratio(self.tap_count) }
+    pub fn open_ratio(&self) -> f32 { self.ratio(self.open_count) }
+
+    fn ratio(&self, count: usize) -> f32 {
+        if self.note_count == 0 { 0.0 } else { count as f32 / self.note_count as f32 }
+    }
+}
+
+const NPS_WINDOW: f64 = 1.0;
+const NPS_STEP: f64 = 0.5;
+
+impl Song {
+    /// Analyzes one chart, `None` if it doesn't exist
+    pub fn analyze(&self, instrument: Instrument, difficulty: Difficulty) -> Option<ChartStats> {
+        let chart = self.charts.get(&(instrument, difficulty))?;
+        let tempo_map = self.tempo_map.as_ref()?;
+
+        let (ticks, times) = note_positions(chart);
+        let mut stats = ChartStats {
+            note_count: ticks.len(),
+            chord_count: chart.notes.iter().filter(|note| note.is_chord).count(),
+            hopo_count: chart.notes.iter().filter(|note| note.is_hopo).count(),
+            tap_count: chart.notes.iter().filter(|note| note.is_tap()).count(),
+            open_count: chart.notes.iter().filter(|note| note.is_open()).count(),
+            ..Default::default()
+        };
+        // drum "chords" are any note with more than one lane hit
+        if chart.notes.is_empty() {
+            stats.chord_count = chart.drum_notes.iter().filter(|note| note.lanes.count_ones() > 1).count();
+        }
+
+        let (Some(first), Some(last)) = (times.first(), times.last()) else { return Some(stats) };
+
+        let length = last - first;
+        stats.average_nps = if length > 0.0 { times.len() as f64 / length } else { 0.0 };
+        stats.peak_nps = peak_nps(&times);
+
+        let mut time = *first;
+        while time <= *last {
+            let start = times.partition_point(|t| *t < time - NPS_WINDOW / 2.0);
+            let end = times.partition_point(|t| *t < time + NPS_WINDOW / 2.0);
+            stats.nps.push((time, (end - start) as f64 / NPS_WINDOW));
+            time += NPS_STEP;
+        }
+
+        // sections go until the next one starts
+        let sections: Vec<(usize, &String)> = self.events.iter().flatten().filter_map(|(tick, event)| match event {
+            GlobalEvent::Section(name) => Some((*tick, name)),
+            _ => None,
+        }).collect();
+        for (i, (tick, name)) in sections.iter().enumerate() {
+            let end_tick = sections.get(i + 1).map_or(usize::MAX, |next| next.0);
+            let start = ticks.partition_point(|t| t < tick);
+            let end = ticks.partition_point(|t| *t < end_tick);
+            if end > start {
+                stats.section_peaks.push((name.to_string(), peak_nps(&times[start..end])));
+            }
+        }
+
+        // a 16th note is a quarter of a beat
+        let stream_gap = tempo_map.resolution / 4;
+        let mut stream = 1;
+        stats.longest_stream = 1;
+        for pair in ticks.windows(2) {
+            stream = if pair[1] - pair[0] <= stream_gap { stream + 1 } else { 1 };
+            stats.longest_stream = stats.longest_stream.max(stream);
+        }
+
+        // nothing scientific, mostly driven by how dense the chart is overall
+        let raw = stats.average_nps * 0.6
+            + stats.peak_nps * 0.08
+            + stats.chord_ratio() as f64
+            + (stats.longest_stream as f64).ln_1p() * 0.2;
+        stats.difficulty = (raw as f32 - 2.0).clamp(0.0, 6.0);
+
+        Some(stats)
+    }
+}
+
+/// The ticks and times of every note, from `notes` or `drum_notes`
+fn note_positions(chart: &Chart) -> (Vec<usize>, Vec<f64>) {
+    if chart.notes.is_empty() {
+        chart.drum_notes.iter().map(|note| (note.tick, note.time)).unzip()
+    } else {
+        chart.notes.iter().map(|note| (note.tick, note.time)).unzip()
+    }
+}
+
+/// The most notes in any `NPS_WINDOW` long window, `times` has to be sorted
+fn peak_nps(times: &[f64]) -> f64 {
+    let mut peak = 0;
+    let mut end = 0;
+    for (start, time) in times.iter().enumerate() {
+        while end < times.len() && times[end] < time + NPS_WINDOW {
+            end += 1;
+        }
+        peak = peak.max(end - start);
+    }
+    peak as f64 / NPS_WINDOW
+}
@@ -0,0 +1,298 @@
+use std::{collections::BTreeMap, fmt};
+
+use super::{writer, Difficulty, Instrument, Song, TempoMap};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChangeTarget {
+    Note,
+    Starpower,
+    Tempo,
+    TimeSignature,
+    // anything from `[Events]` and local events like solos
+    Event,
+}
+
+/// Everything that changed at one tick for one kind of thing, `old` and `new` are the `.chart` lines without the tick
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub tick: usize,
+    // `(measure, beat)` in the new song, starting from 1
+    pub position: (usize, usize),
+    pub kind: ChangeKind,
+    pub target: ChangeTarget,
+    pub old: Vec<String>,
+    pub new: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SongDiff {
+    // set when the resolution changed, the old song's ticks get scaled to the new resolution before comparing
+    pub resolution: Option<(usize, usize)>,
+    pub sync: Vec<Change>,
+    pub events: Vec<Change>,
+    pub charts: Vec<((Instrument, Difficulty), Vec<Change>)>,
+    pub added_charts: Vec<(Instrument, Difficulty)>,
+    pub removed_charts: Vec<(Instrument, Difficulty)>,
+}
+
+impl SongDiff {
+    pub fn is_empty(&self) -> bool {
+        self.resolution.is_none() && self.sync.is_empty() && self.events.is_empty() && self.charts.is_empty()
+            && self.added_charts.is_empty() && self.removed_charts.is_empty()
+    }
+}
+
+/// Compares two versions of a song, generated charts are ignored since they weren't charted
+pub fn diff(old: &Song, new: &Song) -> SongDiff {
+    let (Some(old_map), Some(new_map)) = (&old.tempo_map, &new.tempo_map) else { return SongDiff::default() };
+    let scale = Scale { from: old_map.resolution, to: new_map.resolution };
+
+    let mut res = SongDiff {
+        resolution: (scale.from != scale.to).then_some((scale.from, scale.to)),
+        ..Default::default()
+    };
+
+    let old_sync = writer::write_sync(old.sync_track.as_deref().unwrap_or_default(), &old.unknown_sync);
+    let new_sync = writer::write_sync(new.sync_track.as_deref().unwrap_or_default(), &new.unknown_sync);
+    res.sync = diff_lines(&old_sync, &new_sync, scale, new_map);
+
+    let old_events = writer::write_events(old.events.as_deref().unwrap_or_default(), &old.unknown_events);
+    let new_events = writer::write_events(new.events.as_deref().unwrap_or_default(), &new.unknown_events);
+    res.events = diff_lines(&old_events, &new_events, scale, new_map);
+
+    let charted = |song: &Song| {
+        let mut keys: Vec<_> = song.charts.iter().filter(|(_, chart)| !chart.is_generated).map(|(key, _)| *key).collect();
+        keys.sort();
+        keys
+    };
+    let old_keys = charted(old);
+    let new_keys = charted(new);
+
+    res.removed_charts = old_keys.iter().filter(|key| !new_keys.contains(key)).copied().collect();
+    for key in new_keys {
+        if !old_keys.contains(&key) {
+            res.added_charts.push(key);
+            continue;
+        }
+
+        let changes = diff_lines(&writer::write_chart(&old.charts[&key]), &writer::write_chart(&new.charts[&key]), scale, new_map);
+        if !changes.is_empty() {
+            res.charts.push((key, changes));
+        }
+    }
+
+    res
+}
+
+/// Converts ticks between resolutions
+#[derive(Clone, Copy)]
+struct Scale {
+    from: usize,
+    to: usize,
+}
+
+impl Scale {
+    fn apply(&self, tick: usize) -> usize {
+        if self.from == self.to { tick } else { (tick * self.to + self.from / 2) / self.from }
+    }
+
+    /// Scales the tick and any lengths in a `tick = ...` line
+    fn line(&self, line: &str) -> Option<(usize, String)> {
+        let (tick, body) = line.split_once(" = ")?;
+        let tick = self.apply(tick.parse().ok()?);
+
+        // notes and phrases end in a length
+        let body = match body.rsplit_once(' ') {
+            Some((start, length)) if body.starts_with(['N', 'S']) && let Ok(length) = length.parse() => {
+                format!("{start} {}", self.apply(length))
+            }
+            _ => body.to_string(),
+        };
+        Some((tick, body))
+    }
+}
+
+fn target(body: &str) -> ChangeTarget {
+    match body.split(' ').next() {
+        Some("N") => ChangeTarget::Note,
+        Some("S") => ChangeTarget::Starpower,
+        Some("B") => ChangeTarget::Tempo,
+        Some("TS") => ChangeTarget::TimeSignature,
+        _ => ChangeTarget::Event,
+    }
+}
+
+/// Groups the lines by tick and target and compares each group
+fn diff_lines(old: &[String], new: &[String], scale: Scale, tempo_map: &TempoMap) -> Vec<Change> {
+    type Groups = BTreeMap<(usize, ChangeTarget), Vec<String>>;
+    let group = |lines: &[String], scale: Scale| {
+        let mut groups = Groups::new();
+        for (tick, body) in lines.iter().filter_map(|line| scale.line(line)) {
+            groups.entry((tick, target(&body))).or_default().push(body);
+        }
+        groups
+    };
+    let mut old = group(old, scale);
+    let new = group(new, Scale { from: 1, to: 1 });
+
+    let mut changes = Vec::new();
+    let mut push = |(tick, target): (usize, ChangeTarget), kind, old, new| changes.push(Change {
+        tick,
+        position: tempo_map.measure_position(tick),
+        kind,
+        target,
+        old,
+        new,
+    });
+
+    for (key, new_lines) in new {
+        match old.remove(&key) {
+            Some(old_lines) if old_lines == new_lines => {}
+            Some(old_lines) => push(key, ChangeKind::Modified, old_lines, new_lines),
+            None => push(key, ChangeKind::Added, Vec::new(), new_lines),
+        }
+    }
+    for (key, old_lines) in old {
+        push(key, ChangeKind::Removed, old_lines, Vec::new());
+    }
+
+    changes.sort_by_key(|change| (change.tick, change.target));
+    changes
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (measure, beat) = self.position;
+        write!(f, "measure {measure} beat {beat} (tick {}): {:?} {:?}", self.tick, self.kind, self.target)?;
+        match self.kind {
+            ChangeKind::Added => write!(f, " {}", self.new.join(", ")),
+            ChangeKind::Removed => write!(f, " {}", self.old.join(", ")),
+            ChangeKind::Modified => write!(f, " {} -> {}", self.old.join(", "), self.new.join(", ")),
+        }
+    }
+}
+
+impl fmt::Display for SongDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no changes");
+        }
+
+        if let Some((old, new)) = self.resolution {
+            writeln!(f, "resolution changed from {old} to {new}")?;
+        }
+        for (name, changes) in [("SyncTrack", &self.sync), ("Events", &self.events)] {
+            if changes.is_empty() { continue; }
+            writeln!(f, "[{name}]")?;
+            for change in changes {
+                writeln!(f, "  {change}")?;
+            }
+        }
+        for (instrument, difficulty) in &self.added_charts {
+            writeln!(f, "added {difficulty:?} {instrument:?}")?;
+        }
+        for (instrument, difficulty) in &self.removed_charts {
+            writeln!(f, "removed {difficulty:?} {instrument:?}")?;
+        }
+        for ((instrument, difficulty), changes) in &self.charts {
+            writeln!(f, "[{difficulty:?} {instrument:?}]")?;
+            for change in changes {
+                writeln!(f, "  {change}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, ChangeKind, ChangeTarget, Scale, SongDiff};
+    use crate::chart::{parse_bytes, Difficulty, HopoSettings, Instrument, Song};
+
+    fn song(resolution: usize, sync: &str, notes: &str) -> Song {
+        let chart = format!("[Song]\n{{\n  Resolution = {resolution}\n}}\n[SyncTrack]\n{{\n{sync}}}\n[ExpertSingle]\n{{\n{notes}}}\n");
+        parse_bytes("test.chart".into(), chart.into_bytes(), false, HopoSettings::default()).unwrap().0
+    }
+
+    // 6/8, so a beat is an eighth note (96 ticks) and a measure is 576 ticks
+    fn old() -> Song {
+        song(192, "  0 = TS 6 3\n  0 = B 120000\n  1152 = B 140000\n", "\
+  0 = N 0 0
+  96 = N 1 0
+  288 = N 2 0
+  576 = S 2 192
+  576 = N 3 0
+")
+    }
+
+    type Summary = (usize, (usize, usize), ChangeKind, ChangeTarget, Vec<String>, Vec<String>);
+
+    fn summary(changes: &[super::Change]) -> Vec<Summary> {
+        changes.iter().map(|change| (change.tick, change.position, change.kind, change.target, change.old.clone(), change.new.clone())).collect()
+    }
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn same_song_has_no_changes() {
+        assert!(diff(&old(), &old()).is_empty());
+    }
+
+    #[test]
+    fn notes_starpower_and_tempos() {
+        let new = song(192, "  0 = TS 6 3\n  0 = B 120000\n  1152 = B 150000\n", "\
+  0 = N 0 0
+  288 = N 2 96
+  576 = S 2 288
+  576 = N 3 0
+  672 = N 4 0
+");
+        let diff: SongDiff = diff(&old(), &new);
+
+        assert_eq!(diff.resolution, None);
+        assert!(diff.events.is_empty() && diff.added_charts.is_empty() && diff.removed_charts.is_empty());
+        assert_eq!(summary(&diff.sync), [
+            (1152, (3, 1), ChangeKind::Modified, ChangeTarget::Tempo, lines(&["B 140000"]), lines(&["B 150000"])),
+        ]);
+
+        assert_eq!(diff.charts.len(), 1);
+        assert_eq!(diff.charts[0].0, (Instrument::Single, Difficulty::Expert));
+        assert_eq!(summary(&diff.charts[0].1), [
+            (96, (1, 2), ChangeKind::Removed, ChangeTarget::Note, lines(&["N 1 0"]), lines(&[])),
+            (288, (1, 4), ChangeKind::Modified, ChangeTarget::Note, lines(&["N 2 0"]), lines(&["N 2 96"])),
+            (576, (2, 1), ChangeKind::Modified, ChangeTarget::Starpower, lines(&["S 2 192"]), lines(&["S 2 288"])),
+            (672, (2, 2), ChangeKind::Added, ChangeTarget::Note, lines(&[]), lines(&["N 4 0"])),
+        ]);
+        assert_eq!(diff.charts[0].1[3].to_string(), "measure 2 beat 2 (tick 672): Added Note N 4 0");
+    }
+
+    #[test]
+    fn resolution_changes_get_scaled() {
+        let scale = Scale { from: 192, to: 480 };
+        assert_eq!(scale.line("192 = N 0 96"), Some((480, "N 0 240".into())));
+        assert_eq!(scale.line("384 = S 2 192"), Some((960, "S 2 480".into())));
+        // tempos and events don't have lengths
+        assert_eq!(scale.line("768 = B 140000"), Some((1920, "B 140000".into())));
+        assert_eq!(scale.line("768 = E solo"), Some((1920, "E solo".into())));
+
+        let new = song(480, "  0 = TS 6 3\n  0 = B 120000\n  2880 = B 140000\n", "\
+  0 = N 0 0
+  240 = N 1 0
+  720 = N 2 0
+  1440 = S 2 480
+  1440 = N 3 0
+");
+        let diff = diff(&old(), &new);
+        assert_eq!(diff.resolution, Some((192, 480)));
+        assert!(diff.sync.is_empty() && diff.charts.is_empty());
+    }
+}
@@ -0,0 +1,118 @@
+use super::{get, number, parse_tick_lines, raw_value, ChartError, Chart, Context, LocalEvent, StarpowerEvent};
+
+// lanes, these are also the bits in `DrumNote::lanes`
+pub const KICK: u8 = 0;
+pub const RED: u8 = 1;
+pub const YELLOW: u8 = 2;
+pub const BLUE: u8 = 3;
+/// Green in 4-lane charts, orange in 5-lane charts
+pub const ORANGE: u8 = 4;
+/// Only used by 5-lane charts
+pub const GREEN: u8 = 5;
+
+// note types that aren't lanes
+pub const DOUBLE_KICK: u8 = 32;
+pub const ACCENT_START: u8 = 33; // + lane
+pub const GHOST_START: u8 = 39; // + lane
+pub const CYMBAL_START: u8 = 64; // + lane, only yellow, blue and green have cymbals
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DrumNote {
+    pub tick: usize,
+    // these are all bitmasks of lanes
+    pub lanes: u8,
+    pub cymbals: u8,
+    pub accents: u8,
+    pub ghosts: u8,
+    pub length: [usize; 6],
+
+    // the kick in this note is an expert+ (2x kick) note
+    pub double_kick: bool,
+
+    pub time: f64, // in seconds
+}
+
+impl DrumNote {
+    pub fn is_cymbal(&self, lane: u8) -> bool { self.cymbals >> lane & 1 == 1 }
+    pub fn is_accent(&self, lane: u8) -> bool { self.accents >> lane & 1 == 1 }
+    pub fn is_ghost(&self, lane: u8) -> bool { self.ghosts >> lane & 1 == 1 }
+}
+
+/// A drum fill, hitting the end of one activates starpower
+#[derive(Debug, Clone, Copy)]
+pub struct DrumFill {
+    pub tick: usize,
+    pub length: usize,
+}
+
+impl Chart {
+    /// 5-lane charts are the only ones with notes in the green lane
+    pub fn is_five_lane(&self) -> bool {
+        self.drum_notes.iter().any(|note| note.lanes >> GREEN & 1 == 1)
+    }
+}
+
+pub fn parse_drums(ctx: &mut Context, i: &mut usize) -> Result<Chart, ChartError> {
+    let mut chart = Chart::default();
+    let mut cur = DrumNote::default();
+
+    parse_tick_lines(ctx, i, |line| {
+        let split = line.split(" = ");
+        let tick: usize = number(split[0])?;
+
+        let val = get(&split, 1, line)?.split(" ");
+
+        match val[0].text.to_lowercase().as_str() {
+            "n" => {
+                let note_text = get(&val, 1, line)?;
+                let note: u8 = number(note_text)?;
+                let length = number(get(&val, 2, line)?)?;
+
+                if cur.tick != tick {
+                    if cur.lanes != 0 {
+                        chart.drum_notes.push(cur);
+                    }
+                    cur = DrumNote { tick, ..Default::default() };
+                }
+
+                match note {
+                    KICK..=GREEN => {
+                        cur.lanes |= 1 << note;
+                        cur.length[note as usize] = length;
+                    }
+                    DOUBLE_KICK => {
+                        cur.lanes |= 1 << KICK;
+                        cur.length[KICK as usize] = length;
+                        cur.double_kick = true;
+                    }
+                    ACCENT_START..=38 => cur.accents |= 1 << (note - ACCENT_START),
+                    GHOST_START..=44 => cur.ghosts |= 1 << (note - GHOST_START),
+                    66..=68 => cur.cymbals |= 1 << (note - CYMBAL_START),
+                    _ => return Err((note_text.offset, "unknown drum note type")),
+                }
+            }
+            "s" => {
+                let length = || number(get(&val, 2, line)?);
+                match get(&val, 1, line)?.text {
+                    "2" => chart.starpower_events.push(StarpowerEvent::new(tick, length()?)),
+                    "64" => chart.drum_fills.push(DrumFill { tick, length: length()? }),
+                    _ => chart.unknown_events.push((tick, raw_value(line))),
+                }
+            }
+            "e" => {
+                match get(&val, 1, line)?.text {
+                    "solo" => chart.local_events.push((tick, 0.0, LocalEvent::SoloStart)),
+                    "soloend" => chart.local_events.push((tick, 0.0, LocalEvent::SoloEnd)),
+                    _ => chart.unknown_events.push((tick, raw_value(line))),
+                }
+            }
+            _ => chart.unknown_events.push((tick, raw_value(line))),
+        }
+        Ok(())
+    })?;
+    if cur.lanes != 0 {
+        chart.drum_notes.push(cur);
+    }
+
+    Ok(chart)
+}
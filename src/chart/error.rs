@@ -0,0 +1,57 @@
+use std::{error::Error, fmt, io, string::FromUtf8Error};
+
+#[derive(Debug)]
+pub enum ChartError {
+    Io(io::Error),
+    Encoding(FromUtf8Error),
+    /// A line that couldn't be parsed, `line` and `column` start at 1
+    Malformed {
+        file: String,
+        line: usize,
+        column: usize,
+        section: String,
+        text: String,
+        reason: &'static str,
+    },
+    /// A section that's needed to play the chart
+    MissingSection { file: String, section: &'static str },
+    MissingResolution { file: String },
+    NoTempo { file: String },
+}
+
+impl fmt::Display for ChartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChartError::Io(err) => write!(f, "failed to read chart: {err}"),
+            ChartError::Encoding(err) => write!(f, "chart is not valid utf-8: {err}"),
+            ChartError::Malformed { file, line, column, section, text, reason } => {
+                write!(f, "{file}:{line}:{column}: {reason} in `[{section}]`: `{text}`")
+            }
+            ChartError::MissingSection { file, section } => write!(f, "{file}: chart has no `[{section}]` section"),
+            ChartError::MissingResolution { file } => write!(f, "{file}: chart has no resolution"),
+            ChartError::NoTempo { file } => write!(f, "{file}: chart has no tempo events"),
+        }
+    }
+}
+
+impl Error for ChartError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ChartError::Io(err) => Some(err),
+            ChartError::Encoding(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ChartError {
+    fn from(err: io::Error) -> Self {
+        ChartError::Io(err)
+    }
+}
+
+impl From<FromUtf8Error> for ChartError {
+    fn from(err: FromUtf8Error) -> Self {
+        ChartError::Encoding(err)
+    }
+}
@@ -0,0 +1,177 @@
+use super::{Chart, Difficulty, Instrument, LocalEvent, Song, SyncEvent};
+
+/// 64 bit FNV-1a, std's hashers aren't guaranteed to give the same result between versions so we can't use them for saved scores
+pub struct StableHasher(u64);
+
+impl StableHasher {
+    pub fn new() -> Self { StableHasher(0xcbf29ce484222325) }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    // always 8 bytes so the hash doesn't depend on the platform's pointer size
+    pub fn write_usize(&mut self, value: usize) { self.write(&(value as u64).to_le_bytes()); }
+
+    pub fn finish(&self) -> u64 { self.0 }
+}
+
+impl Default for StableHasher {
+    fn default() -> Self { Self::new() }
+}
+
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = StableHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+impl Song {
+    /// Hashes one track along with everything that affects how it plays, `None` if it doesn't exist
+    /// That's the resolution, tempos and offset, and the notes after song.ini's HOPO and sustain settings are applied
+    /// Unlike `Song::hash` this stays the same when other tracks or the metadata get edited
+    pub fn track_hash(&self, instrument: Instrument, difficulty: Difficulty) -> Option<u64> {
+        let chart = self.charts.get(&(instrument, difficulty))?;
+
+        let mut hasher = StableHasher::new();
+        let (resolution, offset) = self.tempo_map.as_ref().map_or((0, 0.0), |tempo_map| (tempo_map.resolution, tempo_map.offset));
+        hasher.write_usize(resolution);
+        // the chart offset and song.ini delay, in microseconds
+        hasher.write(&((offset * 1_000_000.0).round() as i64).to_le_bytes());
+        // anchors don't change the timing, so they don't change the hash either
+        let sync_track = self.sync_track.iter().flatten().filter(|(_, event)| !matches!(event, SyncEvent::Anchor { .. }));
+        for (tick, event) in sync_track {
+            hasher.write_usize(*tick);
+            match event {
+                SyncEvent::Tempo(tempo) => {
+                    hasher.write(b"B");
+                    // the exact value from the chart, in thousandths of a beat per minute
+                    hasher.write(&((tempo.bpm * 1000.0).round() as u64).to_le_bytes());
+                }
+                SyncEvent::TimeSignature { num, den } => {
+                    hasher.write(b"TS");
+                    hasher.write_usize(*num);
+                    hasher.write_usize(*den);
+                }
+                SyncEvent::Anchor { .. } => {}
+            }
+        }
+        hash_chart(&mut hasher, chart);
+
+        Some(hasher.finish())
+    }
+}
+
+fn hash_chart(hasher: &mut StableHasher, chart: &Chart) {
+    // every list is prefixed with its length so moving things between them changes the hash
+    hasher.write_usize(chart.notes.len());
+    for note in &chart.notes {
+        hasher.write_usize(note.tick);
+        hasher.write(&note.frets.to_le_bytes());
+        // the HOPO settings can change this without touching the flags
+        hasher.write(&[note.is_hopo as u8]);
+        // already trimmed by `sustain_cutoff_threshold`
+        for length in note.length {
+            hasher.write_usize(length);
+        }
+    }
+
+    hasher.write_usize(chart.drum_notes.len());
+    for note in &chart.drum_notes {
+        hasher.write_usize(note.tick);
+        hasher.write(&[note.lanes, note.cymbals, note.accents, note.ghosts, note.double_kick as u8]);
+        for length in note.length {
+            hasher.write_usize(length);
+        }
+    }
+
+    hasher.write_usize(chart.starpower_events.len());
+    for phrase in &chart.starpower_events {
+        hasher.write_usize(phrase.tick);
+        hasher.write_usize(phrase.length);
+    }
+
+    hasher.write_usize(chart.drum_fills.len());
+    for fill in &chart.drum_fills {
+        hasher.write_usize(fill.tick);
+        hasher.write_usize(fill.length);
+    }
+
+    hasher.write_usize(chart.local_events.len());
+    for (tick, _, event) in &chart.local_events {
+        hasher.write_usize(*tick);
+        hasher.write(match event {
+            LocalEvent::SoloStart => b"solo".as_slice(),
+            LocalEvent::SoloEnd => b"soloend".as_slice(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chart::{parse_bytes, Difficulty, HopoSettings, Instrument, Song};
+
+    const CHART: &str = "\
+[Song]
+{
+  Resolution = 192
+}
+[SyncTrack]
+{
+  0 = B 120000
+}
+[ExpertSingle]
+{
+  0 = N 0 0
+  80 = N 1 20
+  160 = N 2 0
+}
+";
+
+    fn song(hopo: HopoSettings) -> Song {
+        parse_bytes("test.chart".into(), CHART.as_bytes().to_vec(), false, hopo).unwrap().0
+    }
+
+    fn hash(song: &Song) -> u64 {
+        song.track_hash(Instrument::Single, Difficulty::Expert).unwrap()
+    }
+
+    #[test]
+    fn same_chart_same_hash() {
+        assert_eq!(hash(&song(HopoSettings::default())), hash(&song(HopoSettings::default())));
+        assert!(song(HopoSettings::default()).track_hash(Instrument::Single, Difficulty::Hard).is_some());
+        assert_eq!(song(HopoSettings::default()).track_hash(Instrument::Drums, Difficulty::Expert), None);
+    }
+
+    #[test]
+    fn ini_settings_change_the_hash() {
+        let base = hash(&song(HopoSettings::default()));
+
+        let hopo_frequency = song(HopoSettings { frequency: Some(100), ..Default::default() });
+        assert_ne!(base, hash(&hopo_frequency));
+
+        let eighthnote_hopo = song(HopoSettings { eighthnote_hopo: true, ..Default::default() });
+        assert_ne!(base, hash(&eighthnote_hopo));
+
+        let mut delayed = song(HopoSettings::default());
+        delayed.delay(0.5);
+        assert_ne!(base, hash(&delayed));
+
+        let mut trimmed = song(HopoSettings::default());
+        trimmed.trim_sustains(50);
+        assert_ne!(base, hash(&trimmed));
+    }
+
+    #[test]
+    fn every_millibeat_changes_the_hash() {
+        // these two are the same number as an f32
+        let tempo = |bpm: &str| {
+            let chart = CHART.replace("B 120000", bpm);
+            hash(&parse_bytes("test.chart".into(), chart.into_bytes(), false, HopoSettings::default()).unwrap().0)
+        };
+        assert_ne!(tempo("B 16777216"), tempo("B 16777217"));
+    }
+}
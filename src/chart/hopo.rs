@@ -0,0 +1,175 @@
+use super::{Chart, Difficulty, SongIni};
+
+/// Decides how far apart notes can be for natural HOPOs, from song.ini's `hopo_frequency` and `eighthnote_hopo`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HopoSettings {
+    // in ticks, takes priority over `eighthnote_hopo`
+    pub frequency: Option<usize>,
+    pub eighthnote_hopo: bool,
+}
+
+impl HopoSettings {
+    pub fn from_ini(ini: &SongIni) -> Self {
+        HopoSettings {
+            frequency: ini.get_parsed("hopo_frequency"),
+            eighthnote_hopo: ini.get("eighthnote_hopo").is_some_and(|value| value == "1" || value.eq_ignore_ascii_case("true")),
+        }
+    }
+
+    /// The most ticks there can be between two notes for the second one to be a natural HOPO
+    pub fn threshold(&self, resolution: usize) -> usize {
+        match self.frequency {
+            Some(frequency) => frequency,
+            None if self.eighthnote_hopo => resolution / 2,
+            // a bit more than a 1/12th note, the same as clone hero
+            None => (65.0 * resolution as f32 / 192.0) as usize,
+        }
+    }
+}
+
+/// Works out which notes are HOPOs, following clone hero's rules:
+/// - a single note is a natural HOPO if it's within `threshold` ticks of the last note
+/// - ...unless it's the same note as the last one, or one of the notes in the last chord
+/// - chords are never natural HOPOs, but they can be forced
+/// - opens count as their own lane, so green -> open can be a HOPO but open -> open can't
+/// - the first note and everything on Easy/Medium are never natural HOPOs
+/// - the forced flag (N 5) flips whatever the natural state is
+/// - taps (N 6) take priority over everything else and are never HOPOs
+pub fn compute_hopos(chart: &mut Chart, difficulty: Difficulty, threshold: usize) {
+    let natural = difficulty >= Difficulty::Hard;
+
+    for i in 0..chart.notes.len() {
+        let (before, after) = chart.notes.split_at_mut(i);
+        let note = &mut after[0];
+
+        note.is_hopo = false;
+        if note.is_tap() { continue; }
+
+        if natural && !note.is_chord && let Some(last_note) = before.last() {
+            let lanes = note.lanes();
+            let last_lanes = last_note.lanes();
+            // `lanes` is a single lane, so this covers both the same note and being part of the last chord
+            let contained = last_lanes & lanes == lanes;
+            note.is_hopo = !contained && note.tick - last_note.tick <= threshold;
+        }
+
+        // forcing
+        note.is_hopo ^= note.frets >> 5 & 1 == 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chart::Note;
+
+    const GREEN: u16 = 1 << 0;
+    const RED: u16 = 1 << 1;
+    const YELLOW: u16 = 1 << 2;
+    const FORCED: u16 = 1 << 5;
+    const TAP: u16 = 1 << 6;
+    const OPEN: u16 = 1 << 7;
+
+    /// Which notes end up as HOPOs
+    fn hopos(notes: &[(usize, u16)], difficulty: Difficulty, threshold: usize) -> Vec<bool> {
+        let mut chart = Chart {
+            notes: notes.iter().map(|&(tick, frets)| Note::new(tick, frets, [0; 9])).collect(),
+            ..Default::default()
+        };
+        compute_hopos(&mut chart, difficulty, threshold);
+        chart.notes.iter().map(|note| note.is_hopo).collect()
+    }
+
+    /// Green then red `gap` ticks later, is the red a HOPO with these settings
+    fn red_is_hopo(settings: HopoSettings, gap: usize) -> bool {
+        hopos(&[(0, GREEN), (gap, RED)], Difficulty::Expert, settings.threshold(192))[1]
+    }
+
+    #[test]
+    fn default_threshold() {
+        let settings = HopoSettings::default();
+        assert_eq!(settings.threshold(192), 65);
+        assert_eq!(settings.threshold(480), 162);
+
+        // 1/12th notes are HOPOs, 1/8th notes aren't
+        assert!(red_is_hopo(settings, 64));
+        assert!(red_is_hopo(settings, 65));
+        assert!(!red_is_hopo(settings, 66));
+        assert!(!red_is_hopo(settings, 96));
+    }
+
+    #[test]
+    fn hopo_frequency() {
+        let settings = HopoSettings::from_ini(&SongIni::parse("[song]\nhopo_frequency = 100\n"));
+        assert_eq!(settings.threshold(192), 100);
+
+        assert!(red_is_hopo(settings, 96));
+        assert!(red_is_hopo(settings, 100));
+        assert!(!red_is_hopo(settings, 101));
+    }
+
+    #[test]
+    fn eighthnote_hopo() {
+        for value in ["1", "True"] {
+            let settings = HopoSettings::from_ini(&SongIni::parse(&format!("[song]\neighthnote_hopo = {value}\n")));
+            assert_eq!(settings.threshold(192), 96);
+
+            assert!(red_is_hopo(settings, 96));
+            assert!(!red_is_hopo(settings, 97));
+        }
+
+        let settings = HopoSettings::from_ini(&SongIni::parse("[song]\neighthnote_hopo = 0\n"));
+        assert_eq!(settings.threshold(192), 65);
+    }
+
+    #[test]
+    fn hopo_frequency_beats_eighthnote_hopo() {
+        let settings = HopoSettings::from_ini(&SongIni::parse("[song]\nhopo_frequency = 50\neighthnote_hopo = 1\n"));
+        assert_eq!(settings.threshold(192), 50);
+
+        assert!(red_is_hopo(settings, 50));
+        assert!(!red_is_hopo(settings, 64));
+    }
+
+    #[test]
+    fn no_natural_hopos_on_easy_and_medium() {
+        let notes = [(0, GREEN), (64, RED), (128, GREEN)];
+        assert_eq!(hopos(&notes, Difficulty::Expert, 65), [false, true, true]);
+        assert_eq!(hopos(&notes, Difficulty::Hard, 65), [false, true, true]);
+        assert_eq!(hopos(&notes, Difficulty::Medium, 65), [false, false, false]);
+        assert_eq!(hopos(&notes, Difficulty::Easy, 65), [false, false, false]);
+    }
+
+    /// The rule being tested, the notes, and which ones should be HOPOs
+    type Rule = (&'static str, &'static [(usize, u16)], &'static [bool]);
+
+    #[test]
+    fn rules() {
+        // everything is 64 ticks apart unless it says otherwise, which is in range by default
+        let table: &[Rule] = &[
+            ("first note", &[(0, RED)], &[false]),
+            ("different note", &[(0, GREEN), (64, RED)], &[false, true]),
+            ("too far apart", &[(0, GREEN), (66, RED)], &[false, false]),
+            ("same note", &[(0, GREEN), (64, GREEN)], &[false, false]),
+            ("chord to contained note", &[(0, GREEN | RED), (64, RED)], &[false, false]),
+            ("chord to other note", &[(0, GREEN | RED), (64, YELLOW)], &[false, true]),
+            // clone hero never makes chords natural HOPOs, they always have to be forced
+            ("chord", &[(0, GREEN), (64, RED | YELLOW)], &[false, false]),
+            ("forced chord", &[(0, GREEN), (64, RED | YELLOW | FORCED)], &[false, true]),
+            ("open to open", &[(0, OPEN), (64, OPEN)], &[false, false]),
+            ("green to open", &[(0, GREEN), (64, OPEN)], &[false, true]),
+            ("open to green", &[(0, OPEN), (64, GREEN)], &[false, true]),
+            ("open chord to open", &[(0, OPEN | GREEN), (64, OPEN)], &[false, false]),
+            ("forced natural HOPO", &[(0, GREEN), (64, RED | FORCED)], &[false, false]),
+            ("forced strum", &[(0, GREEN), (128, RED | FORCED)], &[false, true]),
+            ("forced same note", &[(0, GREEN), (64, GREEN | FORCED)], &[false, true]),
+            ("forced first note", &[(0, GREEN | FORCED)], &[true]),
+            ("tap", &[(0, GREEN), (64, RED | TAP)], &[false, false]),
+            ("tap beats forcing", &[(0, GREEN), (128, RED | FORCED | TAP)], &[false, false]),
+        ];
+
+        for (rule, notes, expected) in table {
+            assert_eq!(hopos(notes, Difficulty::Expert, 65), *expected, "{rule}");
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::{collections::HashMap, fs, io, str::FromStr};
+
+use super::{Instrument, Song};
+
+/// The `[song]` section of a song.ini
+#[derive(Debug, Clone, Default)]
+pub struct SongIni {
+    // keys are lowercase
+    values: HashMap<String, String>,
+}
+
+impl SongIni {
+    pub fn load(file: &str) -> io::Result<Self> {
+        Ok(Self::parse(&decode(&fs::read(file)?)))
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut values = HashMap::new();
+
+        // keys before any section header still count, some inis don't have one at all
+        let mut in_song = true;
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') { continue; }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                // duplicate `[song]` sections just get merged together
+                in_song = line[1..line.len() - 1].trim().eq_ignore_ascii_case("song");
+                continue;
+            }
+
+            if in_song && let Some((key, value)) = line.split_once('=') {
+                // later values override earlier ones, same as clone hero
+                values.insert(key.trim().to_lowercase(), value.trim().to_string());
+            }
+        }
+
+        SongIni { values }
+    }
+
+    /// Builds an ini from key/value pairs, like the metadata in a `.sng`
+    pub fn from_values(values: impl IntoIterator<Item = (String, String)>) -> Self {
+        SongIni { values: values.into_iter().map(|(key, value)| (key.trim().to_lowercase(), value.trim().to_string())).collect() }
+    }
+
+    /// Gets a value by key, empty values count as missing
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(&key.to_lowercase()).map(|value| value.as_str()).filter(|value| !value.is_empty())
+    }
+
+    pub fn get_parsed<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.get(key)?.parse().ok()
+    }
+
+    // in seconds
+    pub fn delay(&self) -> Option<f64> { self.get_parsed::<f64>("delay").map(|delay| delay / 1000.0) }
+    pub fn song_length(&self) -> Option<f64> { self.get_parsed::<f64>("song_length").map(|length| length / 1000.0) }
+    pub fn preview_start(&self) -> Option<f64> { self.get_parsed::<f64>("preview_start_time").map(|time| time / 1000.0) }
+
+    /// The difficulty of an instrument from 0-6, `None` if it's missing or -1
+    pub fn difficulty(&self, instrument: Instrument) -> Option<usize> {
+        self.get_parsed(instrument.ini_difficulty_key()?)
+    }
+}
+
+/// Song metadata from the chart's `[Song]` section, with values from the song.ini on top
+#[derive(Debug, Clone, Default)]
+pub struct SongInfo {
+    pub name: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<String>,
+    pub charter: Option<String>,
+    pub loading_phrase: Option<String>,
+
+    // in seconds
+    pub length: Option<f64>,
+    pub preview_start: Option<f64>,
+
+    pub difficulty: Option<usize>,
+    pub instrument_difficulties: HashMap<Instrument, usize>,
+}
+
+impl Song {
+    pub fn info(&self) -> SongInfo {
+        let metadata = self.metadata.clone().unwrap_or_default();
+        let ini = self.ini.clone().unwrap_or_default();
+        let string = |key: &str, chart: Option<String>| ini.get(key).map(|value| value.to_string()).or(chart);
+
+        let mut instrument_difficulties = HashMap::new();
+        for instrument in self.charts.keys().map(|key| key.0) {
+            if let Some(difficulty) = ini.difficulty(instrument) {
+                instrument_difficulties.insert(instrument, difficulty);
+            }
+        }
+
+        SongInfo {
+            name: string("name", metadata.name),
+            artist: string("artist", metadata.artist),
+            album: string("album", metadata.album),
+            genre: string("genre", metadata.genre),
+            year: string("year", metadata.year),
+            charter: string("charter", metadata.charter).or(ini.get("frets").map(|value| value.into())),
+            loading_phrase: ini.get("loading_phrase").map(|value| value.into()),
+
+            length: ini.song_length().or(metadata.length),
+            preview_start: ini.preview_start().or(metadata.preview_start),
+
+            difficulty: ini.get_parsed("diff_band").or(metadata.difficulty),
+            instrument_difficulties,
+        }
+    }
+}
+
+/// Decodes text in whatever encoding it's in, handling BOMs and falling back to latin-1 for invalid utf-8
+pub fn decode(bytes: &[u8]) -> String {
+    if let Some(rest) = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        return String::from_utf8_lossy(rest).into_owned();
+    }
+    if let Some(rest) = bytes.strip_prefix(&[0xFF, 0xFE]) {
+        return decode_utf16(rest, u16::from_le_bytes);
+    }
+    if let Some(rest) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        return decode_utf16(rest, u16::from_be_bytes);
+    }
+
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.into(),
+        // latin-1 maps directly to the first 256 unicode characters
+        Err(_) => bytes.iter().map(|byte| *byte as char).collect(),
+    }
+}
+
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> String {
+    let units: Vec<u16> = bytes.chunks_exact(2).map(|pair| from_bytes([pair[0], pair[1]])).collect();
+    String::from_utf16_lossy(&units)
+}
@@ -0,0 +1,232 @@
+use std::fmt;
+
+use super::{Chart, ChartError, Difficulty, GlobalEvent, Instrument, LocalEvent, SectionId, Song, SyncEvent};
+
+/// Something in a song that's probably a charting mistake
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintWarning {
+    pub tick: usize,
+    // the `[Events]` section the tick is in, if there is one
+    pub section: Option<String>,
+    // `None` for problems with the whole song
+    pub chart: Option<(Instrument, Difficulty)>,
+    pub kind: LintKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LintKind {
+    /// A sustain that's still going when the next note on the same lane starts
+    OverlappingSustain { lane: usize, next_tick: usize },
+    /// A note on a tick with more than one tempo change, so the tempo in between lasts 0 ticks
+    ZeroLengthTempo,
+    /// A section that's in the file more than once, only the last one is used
+    DuplicateSection(String),
+    StarpowerWithoutNotes,
+    UnmatchedSoloStart,
+    UnmatchedSoloEnd,
+    UnknownSection(String),
+    /// A line that couldn't be parsed and was skipped while loading
+    Malformed(String),
+    /// A midi track we don't know what to do with, it's left out of the song
+    UnhandledMidiTrack(String),
+    /// A midi track with the same notes as an earlier one, only the last one is used
+    DuplicateMidiTrack(String),
+}
+
+impl LintWarning {
+    /// A line that lenient parsing skipped, on the tick it's on if it has one
+    pub(super) fn skipped_line(err: &ChartError) -> Self {
+        let tick = match err {
+            ChartError::Malformed { text, .. } => text.split('=').next().and_then(|tick| tick.trim().parse().ok()).unwrap_or(0),
+            _ => 0,
+        };
+        LintWarning { tick, section: None, chart: None, kind: LintKind::Malformed(err.to_string()) }
+    }
+}
+
+impl fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "tick {}", self.tick)?;
+        if let Some(section) = &self.section {
+            write!(f, " ({section})")?;
+        }
+        if let Some((instrument, difficulty)) = &self.chart {
+            write!(f, " in {}{}", difficulty.chart_name(), instrument.chart_name().unwrap_or("Unknown"))?;
+        }
+        match &self.kind {
+            LintKind::OverlappingSustain { lane, next_tick } => write!(f, ": sustain on lane {lane} overlaps the note at tick {next_tick}"),
+            LintKind::ZeroLengthTempo => write!(f, ": note is on a tempo change that lasts 0 ticks"),
+            LintKind::DuplicateSection(name) => write!(f, ": `[{name}]` is in the chart more than once, only the last one is used"),
+            LintKind::StarpowerWithoutNotes => write!(f, ": star power phrase doesn't have any notes"),
+            LintKind::UnmatchedSoloStart => write!(f, ": solo never ends"),
+            LintKind::UnmatchedSoloEnd => write!(f, ": solo ends without starting"),
+            LintKind::UnknownSection(name) => write!(f, ": unknown section `[{name}]`"),
+            LintKind::Malformed(err) => write!(f, ": skipped {err}"),
+            LintKind::UnhandledMidiTrack(name) => write!(f, ": unhandled midi track `{name}`"),
+            LintKind::DuplicateMidiTrack(name) => write!(f, ": midi contains duplicate note data for `{name}`"),
+        }
+    }
+}
+
+impl Song {
+    /// Looks for charting mistakes, warnings are sorted by tick
+    pub fn lint(&self) -> Vec<LintWarning> {
+        // problems from loading only know their tick, the section is filled in here
+        let mut res: Vec<LintWarning> = self.load_warnings.iter()
+            .map(|warning| LintWarning { section: self.section_at(warning.tick), ..warning.clone() })
+            .collect();
+        let mut warn = |tick, chart, kind| res.push(LintWarning { tick, section: self.section_at(tick), chart, kind });
+
+        // every unknown section gets its own id, so duplicates have to be found by name
+        let mut seen: Vec<&str> = Vec::new();
+        for section in self.layout.iter().flat_map(|layout| &layout.sections) {
+            if seen.contains(&section.name.as_str()) {
+                warn(0, None, LintKind::DuplicateSection(section.name.clone()));
+            }
+            seen.push(&section.name);
+            if matches!(section.id, SectionId::Unknown(_)) {
+                warn(0, None, LintKind::UnknownSection(section.name.clone()));
+            }
+        }
+
+        // two tempos on the same tick means the first one doesn't last at all
+        let tempo_ticks: Vec<usize> = self.sync_track.iter().flatten()
+            .filter(|(_, event)| matches!(event, SyncEvent::Tempo(_)))
+            .map(|(tick, _)| *tick)
+            .collect();
+        let zero_length: Vec<usize> = tempo_ticks.windows(2).filter(|pair| pair[0] == pair[1]).map(|pair| pair[0]).collect();
+
+        let mut charts: Vec<_> = self.charts.iter().filter(|(_, chart)| !chart.is_generated).collect();
+        charts.sort_by_key(|(key, _)| **key);
+        for (key, chart) in charts {
+            let notes = chart_notes(chart);
+
+            for (i, (tick, lanes, length)) in notes.iter().enumerate() {
+                if zero_length.contains(tick) {
+                    warn(*tick, Some(*key), LintKind::ZeroLengthTempo);
+                }
+                for (lane, length) in length.iter().enumerate().filter(|(lane, length)| lanes >> lane & 1 == 1 && **length > 0) {
+                    let next = notes[i + 1..].iter().take_while(|next| next.0 < tick.saturating_add(*length)).find(|next| next.1 >> lane & 1 == 1);
+                    if let Some((next_tick, _, _)) = next {
+                        warn(*tick, Some(*key), LintKind::OverlappingSustain { lane, next_tick: *next_tick });
+                    }
+                }
+            }
+
+            for starpower in &chart.starpower_events {
+                if !notes.iter().any(|(tick, _, _)| (starpower.tick..starpower.tick.saturating_add(starpower.length)).contains(tick)) {
+                    warn(starpower.tick, Some(*key), LintKind::StarpowerWithoutNotes);
+                }
+            }
+
+            let mut solo_start = None;
+            for (tick, _, event) in &chart.local_events {
+                match event {
+                    LocalEvent::SoloStart => if let Some(start) = solo_start.replace(*tick) {
+                        warn(start, Some(*key), LintKind::UnmatchedSoloStart);
+                    }
+                    LocalEvent::SoloEnd => if solo_start.take().is_none() {
+                        warn(*tick, Some(*key), LintKind::UnmatchedSoloEnd);
+                    }
+                }
+            }
+            if let Some(start) = solo_start {
+                warn(start, Some(*key), LintKind::UnmatchedSoloStart);
+            }
+        }
+
+        // sorting is stable so song-wide warnings stay first
+        res.sort_by_key(|warning| warning.tick);
+        res
+    }
+
+    /// The name of the `[Events]` section that `tick` is in
+    fn section_at(&self, tick: usize) -> Option<String> {
+        self.events.iter().flatten()
+            .take_while(|(event_tick, _)| *event_tick <= tick)
+            .filter_map(|(_, event)| match event {
+                GlobalEvent::Section(name) => Some(name.clone()),
+                _ => None,
+            })
+            .last()
+    }
+}
+
+/// `(tick, lanes, sustain lengths)` for every note, guitar or drums
+fn chart_notes(chart: &Chart) -> Vec<(usize, u16, &[usize])> {
+    let notes = chart.notes.iter().map(|note| (note.tick, note.frets, &note.length[..]));
+    let drum_notes = chart.drum_notes.iter().map(|note| (note.tick, note.lanes as u16, &note.length[..]));
+    notes.chain(drum_notes).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LintKind, LintWarning};
+    use crate::chart::{parse_bytes, HopoSettings, Song};
+
+    #[test]
+    fn finds_every_kind_of_mistake() {
+        let chart = "\
+[Song]
+{
+  Resolution = 192
+}
+[SyncTrack]
+{
+  0 = B 120000
+  384 = B 100000
+  384 = B 140000
+}
+[Events]
+{
+  0 = E \"section intro\"
+  384 = E \"section verse\"
+}
+[ExpertSingle]
+{
+  0 = N 0 200
+  0 = E soloend
+  192 = N 0 0
+  192 = N 1 0
+  384 = N 2 0
+  576 = S 2 100
+  768 = E solo
+}
+[ExpertBanjo]
+{
+}
+[ExpertSingle]
+{
+  0 = N 0 200
+  0 = E soloend
+  192 = N 0 0
+  192 = N 1 0
+  384 = N 2 0
+  400 = N zero 0
+  576 = S 2 100
+  768 = E solo
+}
+[ExpertBanjo]
+{
+}
+";
+        // the same as `load` does with the skipped lines
+        let (song, skipped) = parse_bytes("test.chart".into(), chart.as_bytes().to_vec(), true, HopoSettings::default()).unwrap();
+        let song = Song { load_warnings: skipped.iter().map(LintWarning::skipped_line).collect(), ..song };
+        let warnings: Vec<_> = song.lint().into_iter().map(|warning| (warning.tick, warning.section, warning.kind)).collect();
+
+        let section = |name: &str| Some(name.to_string());
+        assert_eq!(warnings, [
+            (0, section("intro"), LintKind::UnknownSection("ExpertBanjo".into())),
+            (0, section("intro"), LintKind::DuplicateSection("ExpertSingle".into())),
+            (0, section("intro"), LintKind::DuplicateSection("ExpertBanjo".into())),
+            (0, section("intro"), LintKind::UnknownSection("ExpertBanjo".into())),
+            (0, section("intro"), LintKind::OverlappingSustain { lane: 0, next_tick: 192 }),
+            (0, section("intro"), LintKind::UnmatchedSoloEnd),
+            (384, section("verse"), LintKind::ZeroLengthTempo),
+            (400, section("verse"), LintKind::Malformed("test.chart:36:11: invalid number in `[ExpertSingle]`: `400 = N zero 0`".into())),
+            (576, section("verse"), LintKind::StarpowerWithoutNotes),
+            (768, section("verse"), LintKind::UnmatchedSoloStart),
+        ]);
+    }
+}
@@ -0,0 +1,133 @@
+use super::{GlobalEvent, TempoMap};
+
+#[derive(Debug, Clone)]
+pub struct Syllable {
+    pub tick: usize,
+    pub time: f64, // in seconds
+
+    // with the markers stripped out
+    pub text: String,
+    // `-` and `=` join this syllable to the next one without a space
+    pub joins_next: bool,
+    // `#` and `^` are spoken or loosely pitched, they're still shown like any other syllable
+    pub is_unpitched: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct LyricPhrase {
+    pub tick: usize,
+    pub end_tick: usize,
+
+    // in seconds
+    pub time: f64,
+    pub end_time: f64,
+
+    pub syllables: Vec<Syllable>,
+}
+
+impl LyricPhrase {
+    /// The whole phrase as it should be displayed
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for syllable in &self.syllables {
+            text += &syllable.text;
+            if !syllable.joins_next { text.push(' '); }
+        }
+        text.trim_end().into()
+    }
+
+    /// How many syllables have been sung by `time`
+    pub fn sung(&self, time: f64) -> usize {
+        self.syllables.partition_point(|syllable| syllable.time <= time)
+    }
+}
+
+/// Parses the markers on a lyric event, `None` if there's nothing to show
+pub fn parse_syllable(tick: usize, lyric: &str) -> Option<Syllable> {
+    let mut text = lyric.trim();
+    let mut joins_next = false;
+    let mut is_unpitched = false;
+    let mut hyphenated = false;
+
+    // markers can be stacked, like `word-#`
+    loop {
+        if let Some(rest) = text.strip_suffix('-') {
+            joins_next = true;
+            text = rest;
+        } else if let Some(rest) = text.strip_suffix('=') {
+            // `=` is a literal hyphen that still joins the syllables
+            joins_next = true;
+            hyphenated = true;
+            text = rest;
+        } else if let Some(rest) = text.strip_suffix(['#', '^']) {
+            is_unpitched = true;
+            text = rest;
+        } else {
+            break;
+        }
+    }
+
+    // `+` on its own is a pitch slide that continues the last syllable, and `$` hides harmony lyrics
+    let mut text: String = text.chars().filter(|c| !matches!(c, '+' | '$' | '#' | '^')).collect();
+    if hyphenated { text.push('-'); }
+    if text.is_empty() { return None; }
+
+    Some(Syllable {
+        tick,
+        time: 0.0,
+        text,
+        joins_next,
+        is_unpitched,
+    })
+}
+
+/// Groups the lyric events into phrases, lyrics outside of a phrase get one that lasts until the next phrase starts
+pub fn build_phrases(events: &[(usize, GlobalEvent)], tempo_map: &TempoMap) -> Vec<LyricPhrase> {
+    let mut phrases = Vec::new();
+    let mut current: Option<LyricPhrase> = None;
+    // whether `current` came from a `phrase_start`
+    let mut from_phrase_start = false;
+
+    let mut finish = |phrase: Option<LyricPhrase>, end_tick: usize| {
+        if let Some(mut phrase) = phrase && !phrase.syllables.is_empty() {
+            phrase.end_tick = end_tick.max(phrase.syllables.last().unwrap().tick);
+            phrases.push(phrase);
+        }
+    };
+    let new_phrase = |tick| LyricPhrase { tick, end_tick: tick, time: 0.0, end_time: 0.0, syllables: Vec::new() };
+
+    for (tick, event) in events {
+        match event {
+            GlobalEvent::PhraseStart => {
+                let mut phrase = new_phrase(*tick);
+                // lyrics on the same tick as the phrase start are sometimes written before it
+                if let Some(implicit) = &mut current && !from_phrase_start {
+                    let split = implicit.syllables.partition_point(|syllable| syllable.tick < *tick);
+                    phrase.syllables = implicit.syllables.split_off(split);
+                }
+                finish(current.replace(phrase), *tick);
+                from_phrase_start = true;
+            }
+            GlobalEvent::PhraseEnd => finish(current.take(), *tick),
+            GlobalEvent::Lyric(lyric) => {
+                if let Some(syllable) = parse_syllable(*tick, lyric) {
+                    if current.is_none() { from_phrase_start = false; }
+                    current.get_or_insert_with(|| new_phrase(*tick)).syllables.push(syllable);
+                }
+            }
+            _ => {}
+        }
+    }
+    let end = current.as_ref().map_or(0, |phrase| phrase.tick);
+    finish(current, end);
+
+    for phrase in &mut phrases {
+        phrase.time = tempo_map.tick_to_seconds(phrase.tick);
+        phrase.end_time = tempo_map.tick_to_seconds(phrase.end_tick);
+        for syllable in &mut phrase.syllables {
+            syllable.time = tempo_map.tick_to_seconds(syllable.tick);
+        }
+    }
+
+    phrases
+}
@@ -0,0 +1,468 @@
+use std::{collections::HashMap, error::Error, fs};
+
+use super::{Chart, Difficulty, GlobalEvent, HopoSettings, Instrument, LintKind, LintWarning, LocalEvent, Note, Song, SongSection, StarpowerEvent, SyncEvent, TempoEvent};
+
+mod export;
+
+pub use export::write;
+
+// base note for each difficulty, the frets go upwards from here (green = base, orange = base + 4)
+const DIFFICULTIES: [(Difficulty, u8); 4] = [
+    (Difficulty::Easy,   60),
+    (Difficulty::Medium, 72),
+    (Difficulty::Hard,   84),
+    (Difficulty::Expert, 96),
+];
+
+// these are relative to the base note
+const FORCE_HOPO: u8 = 5;
+const FORCE_STRUM: u8 = 6;
+
+// these are shared between all difficulties
+const SOLO: u8 = 103;
+const TAP: u8 = 104;
+const VOCAL_PHRASE: u8 = 105;
+const VOCAL_PHRASE_2: u8 = 106;
+const STARPOWER: u8 = 116;
+
+// phase shift sysex events, these are `50 53 00 00 <difficulty> <type> <enable>`
+const SYSEX_HEADER: [u8; 4] = [0x50, 0x53, 0x00, 0x00];
+const SYSEX_ALL_DIFFICULTIES: u8 = 0xFF;
+const SYSEX_OPEN: u8 = 1;
+const SYSEX_TAP: u8 = 4;
+
+#[derive(Debug)]
+enum MidiEvent {
+    NoteOn(u8),
+    NoteOff(u8),
+    Tempo(u32), // in microseconds per quarter note
+    TimeSignature { num: u8, den: u8 },
+    TrackName(String),
+    Text(String),
+    Lyric(String),
+    SysEx(Vec<u8>),
+}
+
+type Track = Vec<(usize, MidiEvent)>;
+
+/// A note from the midi file with its length, as `(tick, length, key)`
+type MidiNote = (usize, usize, u8);
+
+/// A forced HOPO or strum marker, as `(start, end, hopo)`
+type ForceMarker = (usize, usize, bool);
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        let end = self.pos + len;
+        if end > self.data.len() {
+            return Err("unexpected end of midi file".into());
+        }
+
+        let res = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(res)
+    }
+
+    fn u8(&mut self) -> Result<u8, Box<dyn Error>> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a midi variable length quantity
+    fn varlen(&mut self) -> Result<u32, Box<dyn Error>> {
+        let mut res = 0;
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            res = res << 7 | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(res);
+            }
+        }
+
+        Err("invalid variable length quantity in midi file".into())
+    }
+}
+
+/// Parses a Rock Band / Clone Hero style `notes.mid` into a `Song`
+pub fn parse(file: String) -> Result<Song, Box<dyn Error>> {
+    let data = fs::read(&file)?;
+    parse_bytes(&file, &data, HopoSettings::default())
+}
+
+/// Parses midi data that's already in memory, `file` is only used for errors
+pub fn parse_bytes(file: &str, data: &[u8], hopo: HopoSettings) -> Result<Song, Box<dyn Error>> {
+    let (resolution, tracks) = read_midi(data)?;
+
+    let mut song = Song { hash: super::hash::hash_bytes(data), ..Default::default() };
+    let mut sync_track = Vec::new();
+    let mut events = Vec::new();
+    let mut forcing = HashMap::new();
+
+    for (i, track) in tracks.iter().enumerate() {
+        // the tempo map should always be on the first track, but it doesn't hurt to check all of them
+        for (tick, event) in track {
+            match event {
+                MidiEvent::Tempo(tempo) => sync_track.push((*tick, SyncEvent::Tempo(TempoEvent {
+                    bpm: 60_000_000.0 / *tempo as f64,
+                    time: 0.0
+                }))),
+                MidiEvent::TimeSignature { num, den } => sync_track.push((*tick, SyncEvent::TimeSignature {
+                    num: *num as usize,
+                    den: 2usize.checked_pow(*den as u32)
+                        .ok_or_else(|| format!("time signature denominator 2^{den} at tick {tick} is too large"))?
+                })),
+                _ => {}
+            }
+        }
+
+        let name = track.iter().find_map(|(_, event)| match event {
+            MidiEvent::TrackName(name) => Some(name.as_str()),
+            _ => None,
+        }).unwrap_or("");
+
+        // the first track is named after the song
+        if i == 0 {
+            song.metadata = Some(SongSection {
+                name: (!name.is_empty()).then(|| name.into()),
+                resolution: Some(resolution),
+                ..Default::default()
+            });
+            continue;
+        }
+
+        let instrument = match name {
+            "EVENTS" => { parse_events(track, &mut events); continue; }
+            "PART VOCALS" => { parse_vocals(track, &mut events); continue; }
+            "PART GUITAR" | "T1 GEMS" => Instrument::Single,
+            "PART GUITAR COOP" => Instrument::DoubleGuitar,
+            "PART BASS" => Instrument::DoubleBass,
+            "PART RHYTHM" => Instrument::DoubleRhythm,
+            "PART KEYS" => Instrument::Keys,
+            _ => {
+                song.load_warnings.push(LintWarning { tick: 0, section: None, chart: None, kind: LintKind::UnhandledMidiTrack(name.into()) });
+                continue;
+            }
+        };
+
+        for (difficulty, chart, markers) in parse_track(track, resolution) {
+            if song.charts.insert((instrument, difficulty), chart).is_some() {
+                let kind = LintKind::DuplicateMidiTrack(name.into());
+                song.load_warnings.push(LintWarning { tick: 0, section: None, chart: Some((instrument, difficulty)), kind });
+            }
+            forcing.insert((instrument, difficulty), markers);
+        }
+    }
+
+    // sorting is stable, so events on the same tick keep their order
+    sync_track.sort_by_key(|event| event.0);
+    if !sync_track.iter().any(|(tick, event)| *tick == 0 && matches!(event, SyncEvent::Tempo(_))) {
+        // midi files default to 120bpm
+        sync_track.insert(0, (0, SyncEvent::Tempo(TempoEvent { bpm: 120.0, time: 0.0 })));
+    }
+    events.sort_by_key(|event| event.0);
+
+    song.sync_track = Some(sync_track);
+    song.events = Some(events);
+
+    super::postprocess(&mut song, file, hopo)?;
+
+    // midi forcing is absolute instead of toggling the natural state, so convert it to the .chart style flag
+    for (key, chart) in &mut song.charts {
+        let Some(markers) = forcing.get(key) else { continue };
+        // taps ignore forcing
+        for note in chart.notes.iter_mut().filter(|note| !note.is_tap()) {
+            let forced = markers.iter().rev().find(|(start, end, _)| in_range(note.tick, *start, *end));
+            if let Some(&(_, _, hopo)) = forced && note.is_hopo != hopo {
+                note.frets |= 1 << 5;
+                note.is_hopo = hopo;
+            }
+        }
+    }
+
+    super::generate_difficulties(&mut song, hopo);
+
+    Ok(song)
+}
+
+fn read_midi(data: &[u8]) -> Result<(usize, Vec<Track>), Box<dyn Error>> {
+    let mut reader = Reader { data, pos: 0 };
+
+    if reader.bytes(4)? != b"MThd" {
+        return Err("not a midi file".into());
+    }
+    let header_len = reader.u32()? as usize;
+    let header = reader.bytes(header_len)?;
+    if header_len < 6 {
+        return Err("invalid midi header".into());
+    }
+
+    let division = u16::from_be_bytes([header[4], header[5]]);
+    if division & 0x8000 != 0 {
+        return Err("smpte midi timing is not supported".into());
+    }
+
+    let mut tracks = Vec::new();
+    while reader.pos < data.len() {
+        let chunk_type = reader.bytes(4)?;
+        let len = reader.u32()? as usize;
+        let chunk = reader.bytes(len)?;
+
+        // unknown chunks are allowed by the spec and should be ignored
+        if chunk_type == b"MTrk" {
+            tracks.push(read_track(chunk)?);
+        }
+    }
+
+    Ok((division as usize, tracks))
+}
+
+fn read_track(data: &[u8]) -> Result<Track, Box<dyn Error>> {
+    let mut reader = Reader { data, pos: 0 };
+    let mut res = Vec::new();
+    let mut tick = 0;
+    let mut running_status = 0;
+
+    while reader.pos < data.len() {
+        tick += reader.varlen()? as usize;
+
+        let mut status = reader.u8()?;
+        if status < 0x80 {
+            // running status, the byte we just read is actually data
+            if running_status == 0 {
+                return Err("midi track uses running status without a previous status".into());
+            }
+            status = running_status;
+            reader.pos -= 1;
+        }
+
+        match status {
+            // meta and sysex events cancel running status
+            0xFF => {
+                running_status = 0;
+                let kind = reader.u8()?;
+                let len = reader.varlen()? as usize;
+                let data = reader.bytes(len)?;
+                let text = || String::from_utf8_lossy(data).into_owned();
+                match kind {
+                    0x2F => break, // end of track
+                    0x51 if len >= 3 => res.push((tick, MidiEvent::Tempo(u32::from_be_bytes([0, data[0], data[1], data[2]])))),
+                    0x58 if len >= 2 => res.push((tick, MidiEvent::TimeSignature { num: data[0], den: data[1] })),
+                    0x03 => res.push((tick, MidiEvent::TrackName(text()))),
+                    0x05 => res.push((tick, MidiEvent::Lyric(text()))),
+                    0x01..=0x0F => res.push((tick, MidiEvent::Text(text()))),
+                    _ => {}
+                }
+            }
+            0xF0 | 0xF7 => {
+                running_status = 0;
+                let len = reader.varlen()? as usize;
+                res.push((tick, MidiEvent::SysEx(reader.bytes(len)?.to_vec())));
+            }
+            0x80..=0xEF => {
+                running_status = status;
+                let key = reader.u8()?;
+                match status & 0xF0 {
+                    0x80 => {
+                        reader.u8()?;
+                        res.push((tick, MidiEvent::NoteOff(key)));
+                    }
+                    0x90 => {
+                        // a note on with 0 velocity is a note off
+                        if reader.u8()? == 0 {
+                            res.push((tick, MidiEvent::NoteOff(key)));
+                        } else {
+                            res.push((tick, MidiEvent::NoteOn(key)));
+                        }
+                    }
+                    0xC0 | 0xD0 => {} // these only have one data byte
+                    _ => { reader.u8()?; }
+                }
+            }
+            _ => return Err(format!("unsupported midi status byte `{status:#04X}`").into()),
+        }
+    }
+
+    Ok(res)
+}
+
+/// Pairs up note ons and offs into notes with lengths, sorted by tick
+fn pair_notes(track: &Track) -> Vec<MidiNote> {
+    let mut held = HashMap::new();
+    let mut res = Vec::new();
+
+    for (tick, event) in track {
+        match event {
+            MidiEvent::NoteOn(key) => {
+                // retriggering a held note ends the old one
+                if let Some(start) = held.insert(*key, *tick) {
+                    res.push((start, tick - start, *key));
+                }
+            }
+            MidiEvent::NoteOff(key) => {
+                if let Some(start) = held.remove(key) {
+                    res.push((start, tick - start, *key));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    res.sort_by_key(|note| note.0);
+    res
+}
+
+/// Checks if `tick` is inside of a marker, markers with no length still cover their starting tick
+fn in_range(tick: usize, start: usize, end: usize) -> bool {
+    tick >= start && (tick < end || tick == start)
+}
+
+/// Returns every difficulty with notes in the track, along with its forcing markers as `(start, end, hopo)`
+fn parse_track(track: &Track, resolution: usize) -> Vec<(Difficulty, Chart, Vec<ForceMarker>)> {
+    let midi_notes = pair_notes(track);
+
+    // sysex phrases as `(start, end, difficulty, type)`
+    let mut sysex = Vec::new();
+    let mut sysex_starts = HashMap::new();
+    let mut enhanced_opens = false;
+    for (tick, event) in track {
+        match event {
+            MidiEvent::SysEx(data) if data.len() >= 7 && data[..4] == SYSEX_HEADER => {
+                let (difficulty, kind, enable) = (data[4], data[5], data[6] == 1);
+                if enable {
+                    sysex_starts.insert((difficulty, kind), *tick);
+                } else if let Some(start) = sysex_starts.remove(&(difficulty, kind)) {
+                    sysex.push((start, *tick, difficulty, kind));
+                }
+            }
+            MidiEvent::Text(text) if text.trim_matches(['[', ']']) == "ENHANCED_OPENS" => enhanced_opens = true,
+            _ => {}
+        }
+    }
+
+    let mut starpower_events = Vec::new();
+    let mut solos = Vec::new();
+    let mut taps = Vec::new();
+    for &(tick, length, key) in &midi_notes {
+        match key {
+            STARPOWER => starpower_events.push((tick, length)),
+            // `soloend` goes on the last tick of the solo, but midi notes end on the tick after it
+            SOLO => solos.push((tick, tick + length.max(1) - 1)),
+            TAP => taps.push((tick, tick + length)),
+            _ => {}
+        }
+    }
+
+    // rock band charts use short midi notes for notes that aren't sustained
+    let sustain_cutoff = resolution / 3;
+
+    let mut res = Vec::new();
+    for (sysex_difficulty, (difficulty, base)) in DIFFICULTIES.into_iter().enumerate() {
+        let in_sysex = |tick: usize, wanted: u8| sysex.iter().any(|&(start, end, diff, kind)| {
+            kind == wanted && (diff == sysex_difficulty as u8 || diff == SYSEX_ALL_DIFFICULTIES) && in_range(tick, start, end)
+        });
+
+        let mut notes = Vec::new();
+        let mut forcing = Vec::new();
+        let mut last_tick = 0;
+        let mut cur_frets: u16 = 0;
+        let mut cur_length = [0; 9];
+
+        let mut push_note = |tick: usize, mut frets: u16, mut length: [usize; 9]| {
+            if frets == 0 { return; }
+
+            // sysex open notes turn green notes into opens
+            if in_sysex(tick, SYSEX_OPEN) && frets & 1 == 1 {
+                frets = frets & !1 | 1 << 7;
+                length[7] = length[0];
+                length[0] = 0;
+            }
+
+            if taps.iter().any(|&(start, end)| in_range(tick, start, end)) || in_sysex(tick, SYSEX_TAP) {
+                frets |= 1 << 6;
+            }
+
+            notes.push(Note::new(tick, frets, length));
+        };
+
+        for &(tick, length, key) in &midi_notes {
+            let fret = if key >= base && key < base + 5 {
+                key - base
+            } else if enhanced_opens && key == base - 1 {
+                7
+            } else {
+                if key == base + FORCE_HOPO {
+                    forcing.push((tick, tick + length, true));
+                } else if key == base + FORCE_STRUM {
+                    forcing.push((tick, tick + length, false));
+                }
+                continue;
+            };
+
+            if last_tick != tick {
+                push_note(last_tick, cur_frets, cur_length);
+                cur_frets = 0;
+                cur_length = [0; 9];
+            }
+
+            cur_frets |= 1 << fret;
+            cur_length[fret as usize] = if length <= sustain_cutoff { 0 } else { length };
+
+            last_tick = tick;
+        }
+        push_note(last_tick, cur_frets, cur_length);
+
+        if notes.is_empty() { continue; }
+
+        let mut local_events = Vec::new();
+        for &(start, end) in &solos {
+            local_events.push((start, 0.0, LocalEvent::SoloStart));
+            local_events.push((end, 0.0, LocalEvent::SoloEnd));
+        }
+        local_events.sort_by_key(|event| event.0);
+
+        res.push((difficulty, Chart {
+            notes,
+            starpower_events: starpower_events.iter().map(|&(tick, length)| StarpowerEvent::new(tick, length)).collect(),
+            local_events,
+            ..Default::default()
+        }, forcing));
+    }
+
+    res
+}
+
+fn parse_events(track: &Track, events: &mut Vec<(usize, GlobalEvent)>) {
+    for (tick, event) in track {
+        let MidiEvent::Text(text) = event else { continue; };
+        let text = text.trim_matches(['[', ']']);
+
+        if let Some(name) = text.strip_prefix("section ").or_else(|| text.strip_prefix("prc_")) {
+            events.push((*tick, GlobalEvent::Section(name.replace('_', " "))));
+        } else if text == "end" {
+            events.push((*tick, GlobalEvent::SongEnd));
+        }
+    }
+}
+
+fn parse_vocals(track: &Track, events: &mut Vec<(usize, GlobalEvent)>) {
+    for (tick, event) in track {
+        match event {
+            MidiEvent::NoteOn(VOCAL_PHRASE | VOCAL_PHRASE_2) => events.push((*tick, GlobalEvent::PhraseStart)),
+            MidiEvent::NoteOff(VOCAL_PHRASE | VOCAL_PHRASE_2) => events.push((*tick, GlobalEvent::PhraseEnd)),
+            // some older charts put lyrics in text events instead
+            MidiEvent::Lyric(text) | MidiEvent::Text(text) if !text.starts_with('[') => {
+                events.push((*tick, GlobalEvent::Lyric(text.clone())));
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use std::{cmp::Reverse, error::Error};
+
+use super::{DIFFICULTIES, FORCE_HOPO, FORCE_STRUM, SOLO, STARPOWER, SYSEX_HEADER, SYSEX_TAP, VOCAL_PHRASE};
+use crate::chart::{Chart, Difficulty, GlobalEvent, Instrument, LocalEvent, Song, SyncEvent};
+
+const VELOCITY: u8 = 100;
+
+/// An event waiting to be written, sorted by tick and then `order` so note offs come before note ons on the same tick
+struct Event {
+    tick: usize,
+    order: u8,
+    data: Vec<u8>,
+}
+
+#[derive(Default)]
+struct TrackWriter {
+    events: Vec<Event>,
+}
+
+impl TrackWriter {
+    fn meta(&mut self, tick: usize, kind: u8, data: &[u8]) {
+        let mut bytes = vec![0xFF, kind];
+        write_varlen(&mut bytes, data.len() as u32);
+        bytes.extend_from_slice(data);
+        self.events.push(Event { tick, order: 1, data: bytes });
+    }
+
+    fn text(&mut self, tick: usize, text: &str) { self.meta(tick, 0x01, text.as_bytes()); }
+
+    /// Adds a phase shift sysex phrase for one difficulty
+    fn sysex(&mut self, tick: usize, length: usize, difficulty: u8, kind: u8) {
+        for (tick, order, enable) in [(tick, 2, 1), (tick + length.max(1), 0, 0)] {
+            let message = [&SYSEX_HEADER[..], &[difficulty, kind, enable, 0xF7]].concat();
+            let mut data = vec![0xF0];
+            write_varlen(&mut data, message.len() as u32);
+            data.extend(message);
+            self.events.push(Event { tick, order, data });
+        }
+    }
+
+    /// Adds a note on and off, notes always last at least a tick so the off doesn't come before the on
+    fn note(&mut self, tick: usize, length: usize, key: u8) {
+        self.events.push(Event { tick, order: 2, data: vec![0x90, key, VELOCITY] });
+        self.events.push(Event { tick: tick + length.max(1), order: 0, data: vec![0x80, key, 0] });
+    }
+
+    fn finish(mut self, name: &str) -> Vec<u8> {
+        self.events.sort_by_key(|event| (event.tick, event.order));
+        // the name has to be the first event
+        self.meta(0, 0x03, name.as_bytes());
+        self.events.rotate_right(1);
+
+        let mut data = Vec::new();
+        let mut last_tick = 0;
+        for event in self.events {
+            write_varlen(&mut data, (event.tick - last_tick) as u32);
+            data.extend(event.data);
+            last_tick = event.tick;
+        }
+        // end of track
+        data.extend([0x00, 0xFF, 0x2F, 0x00]);
+
+        let mut res = b"MTrk".to_vec();
+        res.extend((data.len() as u32).to_be_bytes());
+        res.extend(data);
+        res
+    }
+}
+
+/// Writes a `Song` as a standard midi file in the same layout as `parse` reads
+/// Only 5 fret guitar-style charts can be exported, drums and GHL are skipped
+pub fn write(song: &Song) -> Result<Vec<u8>, Box<dyn Error>> {
+    let resolution = song.tempo_map.as_ref().map(|tempo_map| tempo_map.resolution).ok_or("song has no tempo map")?;
+    if resolution > 0x7FFF {
+        return Err(format!("resolution {resolution} is too high for a midi file").into());
+    }
+
+    let mut tracks = vec![write_sync(song)?];
+
+    if let Some(events) = &song.events {
+        tracks.push(write_events(events));
+        if events.iter().any(|(_, event)| matches!(event, GlobalEvent::Lyric(_) | GlobalEvent::PhraseStart)) {
+            tracks.push(write_vocals(events));
+        }
+    }
+
+    let mut instruments: Vec<Instrument> = song.charts.keys().map(|(instrument, _)| *instrument).collect();
+    instruments.sort();
+    instruments.dedup();
+    for instrument in instruments {
+        let name = match instrument {
+            Instrument::Single       => "PART GUITAR",
+            Instrument::DoubleGuitar => "PART GUITAR COOP",
+            Instrument::DoubleBass   => "PART BASS",
+            Instrument::DoubleRhythm => "PART RHYTHM",
+            Instrument::Keys         => "PART KEYS",
+            _ => {
+                println!("can't export {instrument:?} to midi");
+                continue;
+            }
+        };
+
+        // generated charts weren't in the original song
+        let mut charts: Vec<(Difficulty, &Chart)> = song.charts.iter()
+            .filter(|((chart_instrument, _), chart)| *chart_instrument == instrument && !chart.is_generated)
+            .map(|((_, difficulty), chart)| (*difficulty, chart))
+            .collect();
+        charts.sort_by_key(|(difficulty, _)| Reverse(*difficulty));
+        if charts.is_empty() { continue; }
+
+        tracks.push(write_instrument(name, &charts));
+    }
+
+    let mut res = b"MThd".to_vec();
+    res.extend(6u32.to_be_bytes());
+    res.extend(1u16.to_be_bytes()); // format 1, multiple tracks played at once
+    res.extend((tracks.len() as u16).to_be_bytes());
+    res.extend((resolution as u16).to_be_bytes());
+    for track in tracks {
+        res.extend(track);
+    }
+
+    Ok(res)
+}
+
+fn write_sync(song: &Song) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut track = TrackWriter::default();
+
+    for (tick, event) in song.sync_track.iter().flatten() {
+        match event {
+            SyncEvent::Tempo(tempo) => {
+                // midi tempos are microseconds per beat in 3 bytes, so anything under ~3.58bpm doesn't fit
+                let micros = (60_000_000.0 / tempo.bpm).round();
+                if !(1.0..=0xFFFFFF as f64).contains(&micros) {
+                    return Err(format!("tempo of {}bpm at tick {tick} can't be written to midi", tempo.bpm).into());
+                }
+                track.meta(*tick, 0x51, &(micros as u32).to_be_bytes()[1..]);
+            }
+            // the denominator is a power of 2, then clocks per click and 32nd notes per quarter
+            SyncEvent::TimeSignature { num, den } => {
+                let num = u8::try_from(*num).map_err(|_| format!("time signature {num}/{den} at tick {tick} can't be written to midi"))?;
+                track.meta(*tick, 0x58, &[num, den.ilog2() as u8, 24, 8]);
+            }
+            // midi has nowhere to put these
+            SyncEvent::Anchor { .. } => {}
+        }
+    }
+
+    let name = song.metadata.as_ref().and_then(|metadata| metadata.name.as_deref()).unwrap_or("");
+    Ok(track.finish(name))
+}
+
+fn write_events(events: &[(usize, GlobalEvent)]) -> Vec<u8> {
+    let mut track = TrackWriter::default();
+
+    for (tick, event) in events {
+        match event {
+            GlobalEvent::Section(name) => track.text(*tick, &format!("[section {name}]")),
+            GlobalEvent::SongEnd => track.text(*tick, "[end]"),
+            _ => {}
+        }
+    }
+
+    track.finish("EVENTS")
+}
+
+fn write_vocals(events: &[(usize, GlobalEvent)]) -> Vec<u8> {
+    let mut track = TrackWriter::default();
+
+    let mut phrase_start = None;
+    for (tick, event) in events {
+        match event {
+            GlobalEvent::Lyric(lyric) => track.meta(*tick, 0x05, lyric.as_bytes()),
+            GlobalEvent::PhraseStart => {
+                // a phrase starting without the last one ending ends it here
+                if let Some(start) = phrase_start.replace(*tick) {
+                    track.note(start, tick - start, VOCAL_PHRASE);
+                }
+            }
+            GlobalEvent::PhraseEnd => {
+                if let Some(start) = phrase_start.take() {
+                    track.note(start, tick - start, VOCAL_PHRASE);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    track.finish("PART VOCALS")
+}
+
+/// `charts` has to be sorted hardest to easiest, star power and solos are shared between difficulties in midi so they come from the hardest one
+fn write_instrument(name: &str, charts: &[(Difficulty, &Chart)]) -> Vec<u8> {
+    let mut track = TrackWriter::default();
+
+    if charts.iter().any(|(_, chart)| chart.notes.iter().any(|note| note.is_open())) {
+        track.text(0, "[ENHANCED_OPENS]");
+    }
+
+    let hardest = charts[0].1;
+    for phrase in &hardest.starpower_events {
+        track.note(phrase.tick, phrase.length, STARPOWER);
+    }
+
+    let mut solo_start = None;
+    for (tick, _, event) in &hardest.local_events {
+        match event {
+            LocalEvent::SoloStart => solo_start = Some(*tick),
+            LocalEvent::SoloEnd => {
+                // `soloend` is on the last note of the solo, midi markers don't include the tick they end on
+                if let Some(start) = solo_start.take() {
+                    track.note(start, tick - start + 1, SOLO);
+                }
+            }
+        }
+    }
+
+    for (difficulty, chart) in charts {
+        // sysex events number the difficulties in the same order as `DIFFICULTIES`
+        let Some(sysex_difficulty) = DIFFICULTIES.iter().position(|(d, _)| d == difficulty) else { continue };
+        let base = DIFFICULTIES[sysex_difficulty].1;
+
+        for note in &chart.notes {
+            for fret in 0..5 {
+                if note.frets >> fret & 1 == 1 {
+                    track.note(note.tick, note.length[fret], base + fret as u8);
+                }
+            }
+            if note.is_open() {
+                track.note(note.tick, note.length[7], base - 1);
+            }
+
+            // the tap note is shared between every difficulty, so use sysex to only tap this one
+            if note.is_tap() {
+                track.sysex(note.tick, 1, sysex_difficulty as u8, SYSEX_TAP);
+            } else if note.frets >> 5 & 1 == 1 {
+                // midi forcing is absolute, so write whatever state the flag ended up giving the note
+                track.note(note.tick, 1, base + if note.is_hopo { FORCE_HOPO } else { FORCE_STRUM });
+            }
+        }
+    }
+
+    track.finish(name)
+}
+
+fn write_varlen(data: &mut Vec<u8>, mut value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    value >>= 7;
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    data.extend(bytes.iter().rev());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write;
+    use crate::chart::{midi, parse_bytes, Difficulty, HopoSettings, Instrument, Song};
+
+    fn export_and_import(song: &Song) -> Song {
+        midi::parse_bytes("test.mid", &write(song).unwrap(), HopoSettings::default()).unwrap()
+    }
+
+    #[test]
+    fn keys_and_solos_survive_round_trips() {
+        let chart = "\
+[Song]
+{
+  Resolution = 192
+}
+[SyncTrack]
+{
+  0 = B 120000
+}
+[ExpertKeys]
+{
+  0 = N 0 0
+  192 = E solo
+  192 = N 1 0
+  384 = N 2 0
+  384 = E soloend
+  576 = N 3 0
+}
+";
+        let (song, _) = parse_bytes("test.chart".into(), chart.as_bytes().to_vec(), false, HopoSettings::default()).unwrap();
+        let key = (Instrument::Keys, Difficulty::Expert);
+        let original = &song.charts[&key];
+
+        let mut imported = export_and_import(&song);
+        for _ in 0..2 {
+            let chart = &imported.charts[&key];
+            assert_eq!(chart.notes.iter().map(|note| (note.tick, note.frets)).collect::<Vec<_>>(),
+                original.notes.iter().map(|note| (note.tick, note.frets)).collect::<Vec<_>>());
+            assert_eq!(chart.local_events, original.local_events);
+            assert_eq!(chart.solos[0].notes, original.solos[0].notes);
+
+            imported = export_and_import(&imported);
+        }
+    }
+
+    #[test]
+    fn sync_events_midi_cant_hold_are_errors() {
+        let sync = |line: &str| {
+            let chart = format!("[Song]\n{{\n  Resolution = 192\n}}\n[SyncTrack]\n{{\n  0 = B 120000\n  {line}\n}}\n");
+            write(&parse_bytes("test.chart".into(), chart.into_bytes(), false, HopoSettings::default()).unwrap().0)
+        };
+
+        assert!(sync("192 = TS 255").is_ok());
+        assert!(sync("192 = TS 256").is_err());
+        assert!(sync("192 = B 3577").is_ok());
+        assert!(sync("192 = B 3576").is_err());
+    }
+}
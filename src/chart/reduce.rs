@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use super::{hopo, Chart, Difficulty, Instrument, Note, StarpowerEvent};
+
+/// Fills in Hard, Medium and Easy from Expert for every instrument that's missing them
+/// Drums and GHL aren't reduced since their lanes don't work the same way
+/// The Expert charts need their HOPOs worked out already, see `reduce`
+pub fn fill_missing_difficulties(charts: &mut HashMap<(Instrument, Difficulty), Chart>, resolution: usize, hopo_threshold: usize) {
+    let instruments: Vec<Instrument> = charts.keys()
+        .filter(|(instrument, difficulty)| *difficulty == Difficulty::Expert
+            && !instrument.is_ghl() && !matches!(instrument, Instrument::Drums | Instrument::Unknown))
+        .map(|(instrument, _)| *instrument)
+        .collect();
+
+    for instrument in instruments {
+        for difficulty in [Difficulty::Hard, Difficulty::Medium, Difficulty::Easy] {
+            if charts.contains_key(&(instrument, difficulty)) { continue; }
+
+            let reduced = reduce(&charts[&(instrument, Difficulty::Expert)], difficulty, resolution, hopo_threshold);
+            charts.insert((instrument, difficulty), reduced);
+        }
+    }
+}
+
+/// Makes an easier version of a (5 fret) Expert chart:
+/// - notes are thinned out to a beat grid, notes off of the grid stay if they're far enough from the last one
+/// - frets above the highest one for the difficulty get moved down, and chords get cut down to fit
+/// - Hard drops taps but keeps every other note as a HOPO or strum like it is on Expert, Medium and Easy only have strums
+/// - Easy also drops open notes
+///
+/// `expert` needs its HOPOs worked out already, `hopo_threshold` should be the same one used for that
+pub fn reduce(expert: &Chart, difficulty: Difficulty, resolution: usize, hopo_threshold: usize) -> Chart {
+    let (grid, highest_fret, max_chord) = match difficulty {
+        Difficulty::Expert => return copy(expert),
+        Difficulty::Hard   => (resolution / 2, 4, 3), // 8th notes, GRYBO
+        Difficulty::Medium => (resolution, 3, 2),     // quarter notes, GRYB
+        Difficulty::Easy   => (resolution, 2, 1),     // quarter notes, GRY
+    };
+    let grid = grid.max(1);
+
+    let mut chart = copy(expert);
+    chart.notes.clear();
+    chart.is_generated = true;
+
+    // the expert note each reduced note came from
+    let mut sources = Vec::new();
+    let mut last_tick = None;
+    for note in &expert.notes {
+        if last_tick.is_some_and(|last| note.tick - last < grid && !note.tick.is_multiple_of(grid)) { continue; }
+        // something on the grid still can't be right after the last note
+        if last_tick.is_some_and(|last| note.tick - last < grid / 2) { continue; }
+
+        let Some(reduced) = reduce_note(note, difficulty, highest_fret, max_chord) else { continue };
+        chart.notes.push(reduced);
+        sources.push(note);
+        last_tick = Some(note.tick);
+    }
+
+    // moving frets around can put a sustain over the next note on the same fret
+    for i in 0..chart.notes.len() {
+        let (current, rest) = chart.notes.split_at_mut(i + 1);
+        let note = &mut current[i];
+        for fret in [0, 1, 2, 3, 4, 7] {
+            if let Some(next) = rest.iter().find(|next| next.frets >> fret & 1 == 1) {
+                note.length[fret] = note.length[fret].min(next.tick - note.tick);
+            }
+        }
+    }
+
+    // the forced flag flips the natural state, and thinning out the notes changes what's natural,
+    // so the flag can't just be copied over, it has to be worked out again from the final state on Expert
+    if difficulty == Difficulty::Hard {
+        hopo::compute_hopos(&mut chart, difficulty, hopo_threshold);
+        for (note, source) in chart.notes.iter_mut().zip(sources) {
+            // taps become regular notes, so let them be whatever's natural
+            if !source.is_tap() && note.is_hopo != source.is_hopo {
+                note.frets |= 1 << 5;
+            }
+        }
+    }
+
+    chart
+}
+
+fn reduce_note(note: &Note, difficulty: Difficulty, highest_fret: usize, max_chord: usize) -> Option<Note> {
+    let mut lengths = [0; 9];
+    let mut frets: Vec<usize> = Vec::new();
+    for fret in 0..5 {
+        if note.frets >> fret & 1 == 0 { continue; }
+
+        let new_fret = fret.min(highest_fret);
+        lengths[new_fret] = lengths[new_fret].max(note.length[fret]);
+        if !frets.contains(&new_fret) { frets.push(new_fret); }
+    }
+
+    // keep the lowest and highest frets of a chord so it keeps its shape
+    while frets.len() > max_chord {
+        frets.remove(if max_chord == 1 { 1 } else { frets.len() / 2 });
+    }
+
+    let mut bits: u16 = frets.iter().map(|fret| 1 << fret).sum();
+    if note.is_open() && difficulty != Difficulty::Easy {
+        bits |= 1 << 7;
+        lengths[7] = note.length[7];
+    }
+    if bits == 0 { return None; }
+
+    let lengths = std::array::from_fn(|fret| if bits >> fret & 1 == 1 { lengths[fret] } else { 0 });
+    Some(Note::new(note.tick, bits, lengths))
+}
+
+/// Copies the notes and phrases, their times get filled in again when the chart is postprocessed
+fn copy(chart: &Chart) -> Chart {
+    Chart {
+        notes: chart.notes.clone(),
+        starpower_events: chart.starpower_events.iter().map(|phrase| StarpowerEvent::new(phrase.tick, phrase.length)).collect(),
+        local_events: chart.local_events.clone(),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chart::{parse_bytes, Difficulty, HopoSettings, Instrument};
+
+    #[test]
+    fn hard_keeps_expert_hopos() {
+        // Hard thins out the red at 48, which leaves the forced yellow far enough from the green to be a strum naturally,
+        // so copying its forced flag would make it a HOPO even though it's a strum on Expert (same for the green at 192)
+        let chart = "\
+[Song]
+{
+  Resolution = 192
+}
+[SyncTrack]
+{
+  0 = B 120000
+}
+[ExpertSingle]
+{
+  0 = N 0 0
+  48 = N 1 0
+  96 = N 2 0
+  96 = N 5 0
+  144 = N 1 0
+  192 = N 0 0
+  192 = N 5 0
+  240 = N 4 0
+  288 = N 3 0
+  288 = N 6 0
+}
+";
+        let (song, _) = parse_bytes("test.chart".into(), chart.as_bytes().to_vec(), false, HopoSettings::default()).unwrap();
+        let expert = &song.charts[&(Instrument::Single, Difficulty::Expert)];
+        let hard = &song.charts[&(Instrument::Single, Difficulty::Hard)];
+        assert!(hard.is_generated);
+
+        let expert_hopos: Vec<(usize, bool)> = expert.notes.iter().map(|note| (note.tick, note.is_hopo)).collect();
+        assert_eq!(expert_hopos, [(0, false), (48, true), (96, false), (144, true), (192, false), (240, true), (288, false)]);
+
+        // taps become regular notes and get their natural state instead
+        let hard_hopos: Vec<(usize, bool)> = hard.notes.iter().map(|note| (note.tick, note.is_hopo)).collect();
+        assert_eq!(hard_hopos, [(0, false), (96, false), (192, false), (288, false)]);
+
+        for difficulty in [Difficulty::Medium, Difficulty::Easy] {
+            assert!(song.charts[&(Instrument::Single, difficulty)].notes.iter().all(|note| !note.is_hopo));
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use std::ops::Range;
+
+use super::{Chart, Difficulty, DrumFill, GlobalEvent, Instrument, Note, Solo, Song, StarpowerEvent, TempoMap};
+
+/// A named section from `[Events]`, which lasts until the next one starts
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    pub tick: usize,
+    pub end_tick: usize,
+
+    // in seconds
+    pub time: f64,
+    pub end_time: f64,
+}
+
+impl Section {
+    pub fn ticks(&self) -> Range<usize> { self.tick..self.end_tick }
+}
+
+impl Song {
+    /// Lists the sections in order, the last one ends at the `end` event or the last note of any chart
+    pub fn sections(&self) -> Vec<Section> {
+        let Some(tempo_map) = &self.tempo_map else { return Vec::new() };
+        let events = self.events.iter().flatten();
+
+        let song_end = events.clone()
+            .find_map(|(tick, event)| matches!(event, GlobalEvent::SongEnd).then_some(*tick))
+            .unwrap_or_else(|| self.charts.values().map(|chart| chart.last_tick().saturating_add(1)).max().unwrap_or(0));
+
+        let starts: Vec<(usize, &String)> = events.filter_map(|(tick, event)| match event {
+            GlobalEvent::Section(name) => Some((*tick, name)),
+            _ => None,
+        }).collect();
+
+        starts.iter().enumerate().map(|(i, (tick, name))| {
+            let end_tick = starts.get(i + 1).map_or(song_end.max(*tick), |next| next.0);
+            Section {
+                name: name.to_string(),
+                tick: *tick,
+                end_tick,
+                time: tempo_map.tick_to_seconds(*tick),
+                end_time: tempo_map.tick_to_seconds(end_tick),
+            }
+        }).collect()
+    }
+
+    /// Slices a chart down to a section, see `Chart::slice`
+    pub fn section_chart(&self, instrument: Instrument, difficulty: Difficulty, section: &Section, rebase: bool) -> Option<Chart> {
+        let chart = self.charts.get(&(instrument, difficulty))?;
+        Some(chart.slice(section.ticks(), rebase, self.tempo_map.as_ref()?))
+    }
+}
+
+impl Chart {
+    /// The tick the last note (or the end of its sustain) is on
+    pub fn last_tick(&self) -> usize {
+        let notes = self.notes.iter().map(|note| note.tick.saturating_add(*note.length.iter().max().unwrap_or(&0)));
+        let drum_notes = self.drum_notes.iter().map(|note| note.tick.saturating_add(*note.length.iter().max().unwrap_or(&0)));
+        notes.chain(drum_notes).max().unwrap_or(0)
+    }
+
+    /// Copies everything in `ticks` into a new chart, cutting off sustains and phrases at the edges
+    /// With `rebase` the ticks and times start from 0 at the start of the range, otherwise they stay the same
+    pub fn slice(&self, ticks: Range<usize>, rebase: bool, tempo_map: &TempoMap) -> Chart {
+        let clip = |tick: usize, length: usize| {
+            let start = tick.max(ticks.start);
+            (start, tick.saturating_add(length).min(ticks.end).saturating_sub(start))
+        };
+        // zero length phrases still cover the tick they're on
+        let overlaps = |tick: usize, length: usize| tick < ticks.end && tick.saturating_add(length.max(1)) > ticks.start;
+
+        let first_note = self.notes.partition_point(|note| note.tick < ticks.start);
+        let last_note = self.notes.partition_point(|note| note.tick < ticks.end);
+        let first_drum_note = self.drum_notes.partition_point(|note| note.tick < ticks.start);
+        let last_drum_note = self.drum_notes.partition_point(|note| note.tick < ticks.end);
+
+        // phrases get new indices, so the notes need to be pointed at them again
+        let mut phrase_indices = vec![None; self.starpower_events.len()];
+        let mut starpower_events = Vec::new();
+        for (i, phrase) in self.starpower_events.iter().enumerate() {
+            if !overlaps(phrase.tick, phrase.length) { continue; }
+            let (tick, length) = clip(phrase.tick, phrase.length);
+            phrase_indices[i] = Some(starpower_events.len());
+            starpower_events.push(StarpowerEvent {
+                tick,
+                length,
+                time: tempo_map.tick_to_seconds(tick),
+                end_time: tempo_map.tick_to_seconds(tick + length),
+            });
+        }
+
+        let mut notes: Vec<Note> = self.notes[first_note..last_note].iter().map(|note| {
+            let mut note = *note;
+            for fret in 0..9 {
+                let (_, length) = clip(note.tick, note.length[fret]);
+                if length != note.length[fret] {
+                    note.length[fret] = length;
+                    note.sustain_end[fret] = tempo_map.tick_to_seconds(note.tick + length);
+                }
+            }
+            note.starpower = note.starpower.and_then(|phrase| phrase_indices[phrase]);
+            note
+        }).collect();
+        // phrases cut off at the end of the range end on a different note now
+        for i in 0..notes.len() {
+            let next = notes.get(i + 1).map(|next| next.starpower);
+            notes[i].is_starpower_end = notes[i].starpower.is_some() && next != Some(notes[i].starpower);
+        }
+
+        let drum_notes = self.drum_notes[first_drum_note..last_drum_note].iter().map(|note| {
+            let mut note = *note;
+            for length in &mut note.length {
+                *length = clip(note.tick, *length).1;
+            }
+            note
+        }).collect();
+
+        let drum_fills = self.drum_fills.iter().filter(|fill| overlaps(fill.tick, fill.length)).map(|fill| {
+            let (tick, length) = clip(fill.tick, fill.length);
+            DrumFill { tick, length }
+        }).collect();
+
+        // solo note indices are into `drum_notes` for drum charts, same as `resolve_solos`
+        let (first, last) = if self.notes.is_empty() { (first_drum_note, last_drum_note) } else { (first_note, last_note) };
+        let solos = self.solos.iter().filter(|solo| solo.tick < ticks.end && solo.end_tick >= ticks.start).map(|solo| {
+            let tick = solo.tick.max(ticks.start);
+            let end_tick = solo.end_tick.min(ticks.end);
+            let notes = solo.notes.start.clamp(first, last) - first..solo.notes.end.clamp(first, last) - first;
+            Solo {
+                tick,
+                end_tick,
+                time: tempo_map.tick_to_seconds(tick),
+                end_time: tempo_map.tick_to_seconds(end_tick),
+                notes,
+            }
+        }).collect();
+
+        let mut chart = Chart {
+            notes,
+            starpower_events,
+            local_events: self.local_events.iter().filter(|(tick, _, _)| ticks.contains(tick)).copied().collect(),
+            solos,
+            drum_notes,
+            drum_fills,
+            unknown_events: self.unknown_events.iter().filter(|(tick, _)| ticks.contains(tick)).cloned().collect(),
+            is_generated: self.is_generated,
+        };
+
+        if rebase {
+            chart.rebase(ticks.start, tempo_map.tick_to_seconds(ticks.start));
+        }
+
+        chart
+    }
+
+    /// Moves everything `ticks` and `seconds` earlier
+    fn rebase(&mut self, ticks: usize, seconds: f64) {
+        for note in &mut self.notes {
+            note.tick -= ticks;
+            note.time -= seconds;
+            for end in &mut note.sustain_end {
+                *end -= seconds;
+            }
+        }
+        for note in &mut self.drum_notes {
+            note.tick -= ticks;
+            note.time -= seconds;
+        }
+        for phrase in &mut self.starpower_events {
+            phrase.tick -= ticks;
+            phrase.time -= seconds;
+            phrase.end_time -= seconds;
+        }
+        for fill in &mut self.drum_fills {
+            fill.tick -= ticks;
+        }
+        for (tick, time, _) in &mut self.local_events {
+            *tick -= ticks;
+            *time -= seconds;
+        }
+        for (tick, _) in &mut self.unknown_events {
+            *tick -= ticks;
+        }
+        for solo in &mut self.solos {
+            solo.tick -= ticks;
+            solo.end_tick -= ticks;
+            solo.time -= seconds;
+            solo.end_time -= seconds;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chart::{parse_bytes, Difficulty, HopoSettings, Instrument, Song};
+
+    // 120bpm at 192 ticks per beat, so 768 ticks is 2 seconds
+    const CHART: &str = "\
+[Song]
+{
+  Resolution = 192
+}
+[SyncTrack]
+{
+  0 = B 120000
+}
+[Events]
+{
+  0 = E \"section intro\"
+  768 = E \"section verse\"
+  1536 = E \"end\"
+}
+[ExpertSingle]
+{
+  0 = N 0 0
+  384 = N 1 0
+  576 = S 2 500
+  576 = N 2 0
+  768 = E solo
+  768 = N 3 0
+  960 = N 4 0
+  960 = E soloend
+  1152 = N 0 768
+}
+[ExpertDrums]
+{
+  0 = N 0 0
+  384 = N 1 0
+  768 = E solo
+  768 = N 2 0
+  960 = N 3 0
+  960 = E soloend
+  1152 = N 1 0
+}
+";
+
+    fn song() -> Song {
+        parse_bytes("test.chart".into(), CHART.as_bytes().to_vec(), false, HopoSettings::default()).unwrap().0
+    }
+
+    #[test]
+    fn sections_end_where_the_next_starts() {
+        let sections: Vec<_> = song().sections().into_iter().map(|section| (section.name, section.tick, section.end_tick, section.time, section.end_time)).collect();
+        assert_eq!(sections, [("intro".into(), 0, 768, 0.0, 2.0), ("verse".into(), 768, 1536, 2.0, 4.0)]);
+    }
+
+    #[test]
+    fn slicing_guitar() {
+        let song = song();
+        let sections = song.sections();
+        let slice = |section, rebase| song.section_chart(Instrument::Single, Difficulty::Expert, &sections[section], rebase).unwrap();
+
+        // the phrase gets cut off at the end of the intro, so it ends on the last note there instead
+        let intro = slice(0, false);
+        assert_eq!(intro.notes.iter().map(|note| note.tick).collect::<Vec<_>>(), [0, 384, 576]);
+        assert_eq!((intro.starpower_events[0].tick, intro.starpower_events[0].length), (576, 192));
+        assert_eq!(intro.notes[2].starpower, Some(0));
+        assert!(intro.notes[2].is_starpower_end);
+        assert!(intro.solos.is_empty());
+
+        let verse = slice(1, false);
+        assert_eq!(verse.notes.iter().map(|note| note.tick).collect::<Vec<_>>(), [768, 960, 1152]);
+        assert_eq!((verse.starpower_events[0].tick, verse.starpower_events[0].length), (768, 308));
+        assert_eq!((verse.solos[0].tick, verse.solos[0].end_tick, verse.solos[0].notes.clone()), (768, 960, 0..2));
+        // the sustain gets cut off at the end of the song
+        assert_eq!(verse.notes[2].length[0], 384);
+        assert_eq!(verse.notes[2].sustain_end[0], 4.0);
+
+        let rebased = slice(1, true);
+        assert_eq!(rebased.notes.iter().map(|note| (note.tick, note.time)).collect::<Vec<_>>(), [(0, 0.0), (192, 0.5), (384, 1.0)]);
+        assert_eq!(rebased.notes.iter().map(|note| note.is_starpower_end).collect::<Vec<_>>(), [false, true, false]);
+        assert_eq!((rebased.starpower_events[0].tick, rebased.starpower_events[0].time), (0, 0.0));
+        assert_eq!((rebased.solos[0].tick, rebased.solos[0].end_tick, rebased.solos[0].time), (0, 192, 0.0));
+        assert_eq!(rebased.notes[2].sustain_end[0], 2.0);
+    }
+
+    #[test]
+    fn slicing_drums() {
+        let song = song();
+        let sections = song.sections();
+        let slice = |section, rebase| song.section_chart(Instrument::Drums, Difficulty::Expert, &sections[section], rebase).unwrap();
+
+        let intro = slice(0, false);
+        assert_eq!(intro.drum_notes.iter().map(|note| note.tick).collect::<Vec<_>>(), [0, 384]);
+        assert!(intro.solos.is_empty());
+
+        let verse = slice(1, false);
+        assert_eq!(verse.drum_notes.iter().map(|note| note.tick).collect::<Vec<_>>(), [768, 960, 1152]);
+        assert_eq!((verse.solos[0].tick, verse.solos[0].notes.clone()), (768, 0..2));
+        assert_eq!(verse.solos[0].note_count(), 2);
+
+        let rebased = slice(1, true);
+        assert_eq!(rebased.drum_notes.iter().map(|note| (note.tick, note.time)).collect::<Vec<_>>(), [(0, 0.0), (192, 0.5), (384, 1.0)]);
+        assert_eq!((rebased.solos[0].tick, rebased.solos[0].time, rebased.solos[0].notes.clone()), (0, 0.0, 0..2));
+    }
+}
@@ -0,0 +1,274 @@
+use std::{fs::File, io::{self, BufReader, Error, ErrorKind, Read, Seek, SeekFrom}, path::{Path, PathBuf}};
+
+use super::SongIni;
+
+/// A file stored inside of a `.sng`
+#[derive(Debug, Clone)]
+pub struct SngEntry {
+    pub name: String,
+    pub length: u64,
+    // from the start of the `.sng`
+    offset: u64,
+}
+
+/// A `.sng` song container, which bundles the song.ini values, chart, audio and images into one file
+/// Only the header is read up front, files are read (and unmasked) when they're needed
+#[derive(Debug, Clone)]
+pub struct SngFile {
+    pub path: PathBuf,
+    pub version: u32,
+    xor_mask: [u8; 16],
+
+    // the same keys as a song.ini
+    pub metadata: Vec<(String, String)>,
+    pub files: Vec<SngEntry>,
+}
+
+impl SngFile {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0; 6];
+        reader.read_exact(&mut magic)?;
+        if &magic != b"SNGPKG" {
+            return Err(Error::new(ErrorKind::InvalidData, "not a sng file"));
+        }
+        let version = read_u32(&mut reader)?;
+        let mut xor_mask = [0; 16];
+        reader.read_exact(&mut xor_mask)?;
+
+        // metadata: section length, count, then length-prefixed key/value pairs
+        let _metadata_len = read_u64(&mut reader)?;
+        let metadata_count = read_u64(&mut reader)?;
+        // each pair is at least its two lengths
+        check_fits(&mut reader, file_len, metadata_count.saturating_mul(8))?;
+        let mut metadata = Vec::new();
+        for _ in 0..metadata_count {
+            let key_len = read_u32(&mut reader)?;
+            let key = read_string(&mut reader, file_len, key_len.into())?;
+            let value_len = read_u32(&mut reader)?;
+            let value = read_string(&mut reader, file_len, value_len.into())?;
+            metadata.push((key, value));
+        }
+
+        // file index: section length, count, then name/length/offset for each file
+        let _index_len = read_u64(&mut reader)?;
+        let file_count = read_u64(&mut reader)?;
+        // each file is at least the name length, the file length and the offset
+        check_fits(&mut reader, file_len, file_count.saturating_mul(17))?;
+        let mut files = Vec::new();
+        for _ in 0..file_count {
+            let mut name_len = [0];
+            reader.read_exact(&mut name_len)?;
+            let name = read_string(&mut reader, file_len, name_len[0].into())?;
+            let length = read_u64(&mut reader)?;
+            let offset = read_u64(&mut reader)?;
+            files.push(SngEntry { name, length, offset });
+        }
+
+        Ok(SngFile {
+            path: path.to_path_buf(),
+            version,
+            xor_mask,
+            metadata,
+            files,
+        })
+    }
+
+    /// The metadata as a song.ini
+    pub fn ini(&self) -> SongIni {
+        SongIni::from_values(self.metadata.iter().cloned())
+    }
+
+    /// Gets a file by name, ignoring case
+    pub fn entry(&self, name: &str) -> Option<&SngEntry> {
+        self.files.iter().find(|file| file.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn contains(&self, name: &str) -> bool { self.entry(name).is_some() }
+
+    /// Opens a file for reading, it gets unmasked as it's read so it never has to be in memory all at once
+    pub fn open_file(&self, name: &str) -> io::Result<SngReader> {
+        let entry = self.entry(name).ok_or_else(|| Error::new(ErrorKind::NotFound, format!("`{name}` isn't in {}", self.path.display())))?;
+
+        let mut file = File::open(&self.path)?;
+        if entry.offset.saturating_add(entry.length) > file.metadata()?.len() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, format!("`{name}` goes past the end of {}", self.path.display())));
+        }
+        file.seek(SeekFrom::Start(entry.offset))?;
+
+        Ok(SngReader {
+            file: BufReader::new(file),
+            xor_mask: self.xor_mask,
+            start: entry.offset,
+            length: entry.length,
+            pos: 0,
+        })
+    }
+
+    /// Reads and unmasks a whole file
+    pub fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.open_file(name)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// A single file inside of a `.sng`, unmasked while it's read
+#[derive(Debug)]
+pub struct SngReader {
+    file: BufReader<File>,
+    xor_mask: [u8; 16],
+    // where the file starts in the `.sng`
+    start: u64,
+    length: u64,
+    // from the start of the file
+    pos: u64,
+}
+
+impl SngReader {
+    pub fn len(&self) -> u64 { self.length }
+    pub fn is_empty(&self) -> bool { self.length == 0 }
+}
+
+impl Read for SngReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = (buf.len() as u64).min(self.length.saturating_sub(self.pos)) as usize;
+        let read = self.file.read(&mut buf[..len])?;
+
+        // the mask index restarts for every file
+        for (i, byte) in buf[..read].iter_mut().enumerate() {
+            let index = self.pos + i as u64;
+            *byte ^= self.xor_mask[(index % 16) as usize] ^ index as u8;
+        }
+
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for SngReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.length.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        }.ok_or_else(|| Error::new(ErrorKind::InvalidInput, "can't seek before the start of the file"))?;
+
+        // seeking past the end is fine, reads there just return nothing
+        self.file.seek(SeekFrom::Start(self.start.saturating_add(pos)))?;
+        self.pos = pos;
+        Ok(pos)
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Lengths in the header can't be trusted, so make sure `len` bytes are actually left before allocating anything for them
+fn check_fits(reader: &mut BufReader<File>, file_len: u64, len: u64) -> io::Result<()> {
+    if len > file_len.saturating_sub(reader.stream_position()?) {
+        return Err(Error::new(ErrorKind::UnexpectedEof, format!("sng header says {len} bytes are next, but the file isn't that long")));
+    }
+    Ok(())
+}
+
+fn read_string(reader: &mut BufReader<File>, file_len: u64, len: u64) -> io::Result<String> {
+    check_fits(reader, file_len, len)?;
+    let mut bytes = vec![0; len as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Seek, SeekFrom};
+
+    use super::SngFile;
+
+    /// Writes a `.sng` with one file in it
+    fn write_sng(path: &std::path::Path, name: &str, contents: &[u8]) {
+        let xor_mask: [u8; 16] = std::array::from_fn(|i| (i * 17 + 3) as u8);
+
+        let mut data = b"SNGPKG".to_vec();
+        data.extend(1u32.to_le_bytes());
+        data.extend(xor_mask);
+        // no metadata
+        data.extend(8u64.to_le_bytes());
+        data.extend(0u64.to_le_bytes());
+
+        let index_len = 8 + 1 + name.len() as u64 + 16;
+        let offset = data.len() as u64 + 8 + index_len + 8;
+        data.extend(index_len.to_le_bytes());
+        data.extend(1u64.to_le_bytes());
+        data.push(name.len() as u8);
+        data.extend(name.as_bytes());
+        data.extend((contents.len() as u64).to_le_bytes());
+        data.extend(offset.to_le_bytes());
+
+        data.extend((contents.len() as u64).to_le_bytes());
+        data.extend(contents.iter().enumerate().map(|(i, byte)| byte ^ xor_mask[i % 16] ^ i as u8));
+        std::fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn reads_and_seeks_masked_files() {
+        let path = std::env::temp_dir().join(format!("open_hero_{}.sng", std::process::id()));
+        let contents: Vec<u8> = (0..1000).map(|i| (i * 7 % 251) as u8).collect();
+        write_sng(&path, "song.ogg", &contents);
+
+        let sng = SngFile::open(&path).unwrap();
+        assert_eq!(sng.read("SONG.OGG").unwrap(), contents);
+
+        let mut reader = sng.open_file("song.ogg").unwrap();
+        let mut buf = [0; 10];
+        reader.seek(SeekFrom::Start(500)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, contents[500..510]);
+
+        reader.seek(SeekFrom::End(-4)).unwrap();
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, contents[996..]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_lengths_past_the_end() {
+        let path = std::env::temp_dir().join(format!("open_hero_lengths_{}.sng", std::process::id()));
+
+        let mut header = b"SNGPKG".to_vec();
+        header.extend(1u32.to_le_bytes());
+        header.extend([0; 16]);
+        header.extend(0u64.to_le_bytes());
+
+        // way more metadata pairs than could fit
+        let mut data = header.clone();
+        data.extend(u64::MAX.to_le_bytes());
+        std::fs::write(&path, data).unwrap();
+        assert!(SngFile::open(&path).is_err());
+
+        // one pair with a key that's 4gb long
+        let mut data = header;
+        data.extend(1u64.to_le_bytes());
+        data.extend(u32::MAX.to_le_bytes());
+        data.extend(b"key");
+        std::fs::write(&path, data).unwrap();
+        assert!(SngFile::open(&path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
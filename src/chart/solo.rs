@@ -0,0 +1,80 @@
+use std::ops::Range;
+
+use super::{Chart, LocalEvent, TempoMap};
+
+#[derive(Debug, Clone)]
+pub struct Solo {
+    pub tick: usize,
+    pub end_tick: usize,
+
+    // in seconds
+    pub time: f64,
+    pub end_time: f64,
+
+    // indices into `Chart::notes`, or `Chart::drum_notes` for drum charts
+    pub notes: Range<usize>,
+}
+
+impl Solo {
+    pub fn note_count(&self) -> usize { self.notes.len() }
+}
+
+impl Chart {
+    /// Gets the solo that's active at `time`, if any
+    pub fn solo_at(&self, time: f64) -> Option<&Solo> {
+        self.solos.iter().find(|solo| solo.time <= time && time <= solo.end_time)
+    }
+
+    /// Gets the solo that the note at `index` is part of, if any
+    pub fn solo_for_note(&self, index: usize) -> Option<&Solo> {
+        self.solos.iter().find(|solo| solo.notes.contains(&index))
+    }
+}
+
+/// Times the local events and pairs the solo markers up into `Chart::solos`
+pub fn resolve_solos(chart: &mut Chart, tempo_map: &TempoMap) {
+    for (tick, time, _) in &mut chart.local_events {
+        *time = tempo_map.tick_to_seconds(*tick);
+    }
+
+    let ticks: Vec<usize> = if chart.notes.is_empty() {
+        chart.drum_notes.iter().map(|note| note.tick).collect()
+    } else {
+        chart.notes.iter().map(|note| note.tick).collect()
+    };
+
+    let mut ranges = Vec::new();
+    let mut start = None;
+    for (tick, _, event) in &chart.local_events {
+        match event {
+            LocalEvent::SoloStart => {
+                // a new solo without ending the old one ends it here
+                if let Some(start) = start.replace(*tick) {
+                    ranges.push((start, *tick));
+                }
+            }
+            LocalEvent::SoloEnd => {
+                if let Some(start) = start.take() {
+                    ranges.push((start, *tick));
+                }
+            }
+        }
+    }
+    // solos that never end go until the last note
+    if let Some(start) = start {
+        ranges.push((start, ticks.last().copied().unwrap_or(start).max(start)));
+    }
+
+    chart.solos = ranges.into_iter().map(|(tick, end_tick)| {
+        // `soloend` is usually on the last note of the solo, so it's inclusive
+        let first = ticks.partition_point(|note| *note < tick);
+        let last = ticks.partition_point(|note| *note <= end_tick);
+        Solo {
+            tick,
+            end_tick,
+            time: tempo_map.tick_to_seconds(tick),
+            end_time: tempo_map.tick_to_seconds(end_tick),
+            notes: first..last.max(first),
+        }
+    }).collect();
+}
@@ -0,0 +1,235 @@
+use super::SyncEvent;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Tempo {
+    pub tick: usize,
+    pub bpm: f64,
+
+    pub time: f64, // in seconds
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TimeSignature {
+    pub tick: usize,
+    pub num: usize,
+    pub den: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Beat {
+    pub tick: usize,
+    pub time: f64, // in seconds
+    // the first beat of a measure
+    pub is_measure: bool,
+}
+
+/// Converts between ticks and seconds using the tempo changes from a `SyncTrack`
+#[derive(Debug, Clone)]
+pub struct TempoMap {
+    pub resolution: usize,
+    // added to every time, positive values make the chart later compared to the audio
+    pub offset: f64,
+    tempos: Vec<Tempo>,
+    time_signatures: Vec<TimeSignature>,
+}
+
+impl TempoMap {
+    pub fn new(sync_track: &[(usize, SyncEvent)], resolution: usize, offset: f64) -> Self {
+        let mut tempos: Vec<Tempo> = Vec::new();
+        let mut time_signatures = Vec::new();
+
+        for (tick, event) in sync_track {
+            match event {
+                SyncEvent::Tempo(tempo) => {
+                    let time = match tempos.last() {
+                        Some(last) => last.time + ticks_to_seconds(tick - last.tick, last.bpm, resolution),
+                        // charts should always start with a tempo, but if they don't assume 120bpm until the first one
+                        None => offset + ticks_to_seconds(*tick, 120.0, resolution),
+                    };
+                    tempos.push(Tempo { tick: *tick, bpm: tempo.bpm, time });
+                }
+                SyncEvent::TimeSignature { num, den } => time_signatures.push(TimeSignature { tick: *tick, num: *num, den: *den }),
+                SyncEvent::Anchor { .. } => {}
+            }
+        }
+
+        if tempos.first().is_none_or(|tempo| tempo.tick != 0) {
+            tempos.insert(0, Tempo { tick: 0, bpm: 120.0, time: offset });
+        }
+        if time_signatures.first().is_none_or(|ts| ts.tick != 0) {
+            time_signatures.insert(0, TimeSignature { tick: 0, num: 4, den: 4 });
+        }
+
+        TempoMap {
+            resolution,
+            offset,
+            tempos,
+            time_signatures,
+        }
+    }
+
+    /// Moves the whole map `seconds` later
+    pub fn delay(&mut self, seconds: f64) {
+        self.offset += seconds;
+        for tempo in &mut self.tempos {
+            tempo.time += seconds;
+        }
+    }
+
+    pub fn tempos(&self) -> &[Tempo] { &self.tempos }
+    pub fn time_signatures(&self) -> &[TimeSignature] { &self.time_signatures }
+
+    /// Gets the tempo change that's active at `tick`
+    pub fn tempo_at(&self, tick: usize) -> &Tempo {
+        // there's always a tempo on tick 0, so this can't underflow
+        &self.tempos[self.tempos.partition_point(|tempo| tempo.tick <= tick) - 1]
+    }
+
+    pub fn bpm_at(&self, tick: usize) -> f64 { self.tempo_at(tick).bpm }
+
+    pub fn time_signature_at(&self, tick: usize) -> &TimeSignature {
+        &self.time_signatures[self.time_signatures.partition_point(|ts| ts.tick <= tick) - 1]
+    }
+
+    pub fn tick_to_seconds(&self, tick: usize) -> f64 {
+        let tempo = self.tempo_at(tick);
+        tempo.time + ticks_to_seconds(tick - tempo.tick, tempo.bpm, self.resolution)
+    }
+
+    /// Converts a time to a (fractional) tick, times before the song extend the first tempo backwards
+    pub fn seconds_to_tick(&self, time: f64) -> f64 {
+        let tempo = &self.tempos[self.tempos.partition_point(|tempo| tempo.time <= time).max(1) - 1];
+        tempo.tick as f64 + (time - tempo.time) * tempo.bpm / 60.0 * self.resolution as f64
+    }
+
+    /// Iterates over every beat from `start` (inclusive) to `end` (exclusive) in ticks
+    /// Beats follow the time signature, so a beat in 6/8 is an eighth note
+    pub fn beats(&self, start: usize, end: usize) -> impl Iterator<Item = Beat> + '_ {
+        let mut ts_index = self.time_signatures.partition_point(|ts| ts.tick <= start) - 1;
+        let mut measure_start = self.time_signatures[ts_index].tick;
+
+        // skip ahead to the measure that contains `start`
+        let ts = &self.time_signatures[ts_index];
+        let measure_len = self.beat_len(ts) * ts.num.max(1);
+        measure_start += (start - measure_start) / measure_len * measure_len;
+
+        let mut tick = measure_start;
+        std::iter::from_fn(move || {
+            loop {
+                // time signature changes always start a new measure
+                if let Some(next) = self.time_signatures.get(ts_index + 1) && tick >= next.tick {
+                    ts_index += 1;
+                    tick = next.tick;
+                    measure_start = next.tick;
+                }
+                if tick >= end { return None; }
+
+                let ts = &self.time_signatures[ts_index];
+                let beat_len = self.beat_len(ts);
+                let beat = Beat {
+                    tick,
+                    time: self.tick_to_seconds(tick),
+                    is_measure: (tick - measure_start).is_multiple_of(beat_len * ts.num.max(1)),
+                };
+                tick += beat_len;
+
+                if beat.tick >= start { return Some(beat); }
+            }
+        })
+    }
+
+    /// Iterates over the first beat of every measure from `start` (inclusive) to `end` (exclusive) in ticks
+    pub fn measures(&self, start: usize, end: usize) -> impl Iterator<Item = Beat> + '_ {
+        self.beats(start, end).filter(|beat| beat.is_measure)
+    }
+
+    /// Gets the measure and beat `tick` is in, both starting from 1
+    pub fn measure_position(&self, tick: usize) -> (usize, usize) {
+        let mut measure = 0;
+        for (i, ts) in self.time_signatures.iter().enumerate() {
+            let beat_len = self.beat_len(ts);
+            let measure_len = beat_len * ts.num.max(1);
+
+            match self.time_signatures.get(i + 1) {
+                // time signature changes always start a new measure, even if the last one isn't finished
+                Some(next) if next.tick <= tick => measure += (next.tick - ts.tick).div_ceil(measure_len),
+                _ => {
+                    let offset = tick - ts.tick;
+                    return (measure + offset / measure_len + 1, offset % measure_len / beat_len + 1);
+                }
+            }
+        }
+        unreachable!("there's always a time signature on tick 0")
+    }
+
+    fn beat_len(&self, ts: &TimeSignature) -> usize {
+        (self.resolution * 4 / ts.den.max(1)).max(1)
+    }
+}
+
+pub fn ticks_to_seconds(ticks: usize, bpm: f64, resolution: usize) -> f64 {
+    ticks as f64 / resolution as f64 * (60.0 / bpm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TempoMap;
+    use crate::chart::{parse_bytes, HopoSettings, SyncEvent, TempoEvent};
+
+    fn tempo(bpm: f64) -> SyncEvent { SyncEvent::Tempo(TempoEvent { bpm, time: 0.0 }) }
+
+    // 120bpm then 240bpm from tick 768 (2 seconds in)
+    fn two_tempos(offset: f64) -> TempoMap {
+        TempoMap::new(&[(0, tempo(120.0)), (768, tempo(240.0))], 192, offset)
+    }
+
+    #[test]
+    fn tempo_changes() {
+        let tempo_map = two_tempos(0.0);
+        assert_eq!(tempo_map.tick_to_seconds(384), 1.0);
+        assert_eq!(tempo_map.tick_to_seconds(768), 2.0);
+        assert_eq!(tempo_map.tick_to_seconds(960), 2.25);
+        assert_eq!(tempo_map.bpm_at(767), 120.0);
+        assert_eq!(tempo_map.bpm_at(768), 240.0);
+
+        let delayed = two_tempos(1.5);
+        assert_eq!(delayed.tick_to_seconds(960), 3.75);
+        // times before the song keep going at the first tempo
+        assert_eq!(delayed.seconds_to_tick(1.0), -192.0);
+    }
+
+    #[test]
+    fn round_trips_at_fractional_ticks() {
+        let tempo_map = two_tempos(0.0);
+        for tick in [0, 1, 383, 767, 768, 769, 960, 10000] {
+            assert!((tempo_map.seconds_to_tick(tempo_map.tick_to_seconds(tick)) - tick as f64).abs() < 1e-9, "{tick}");
+        }
+
+        for (time, tick) in [(1.234, 473.856), (2.1, 844.8), (0.0013, 0.4992)] {
+            assert!((tempo_map.seconds_to_tick(time) - tick).abs() < 1e-9, "{time}");
+        }
+    }
+
+    #[test]
+    fn time_signature_denominators_are_powers_of_2() {
+        // `TS 6 3` is 6/8, so a beat is an eighth note (96 ticks) and a measure is 576 ticks
+        let chart = "[Song]\n{\n  Resolution = 192\n}\n[SyncTrack]\n{\n  0 = TS 6 3\n  0 = B 120000\n  1152 = TS 4\n}\n";
+        let song = parse_bytes("test.chart".into(), chart.as_bytes().to_vec(), false, HopoSettings::default()).unwrap().0;
+        let tempo_map = song.tempo_map.unwrap();
+
+        let ts = tempo_map.time_signature_at(575);
+        assert_eq!((ts.num, ts.den), (6, 8));
+        assert_eq!(tempo_map.measure_position(0), (1, 1));
+        assert_eq!(tempo_map.measure_position(95), (1, 1));
+        assert_eq!(tempo_map.measure_position(672), (2, 2));
+        assert_eq!(tempo_map.measure_position(1151), (2, 6));
+        // back to 4/4
+        assert_eq!(tempo_map.measure_position(1152), (3, 1));
+        assert_eq!(tempo_map.measure_position(1152 + 192 * 5), (4, 2));
+
+        let beats: Vec<(usize, bool)> = tempo_map.beats(480, 1536).map(|beat| (beat.tick, beat.is_measure)).collect();
+        assert_eq!(beats, [(480, false), (576, true), (672, false), (768, false), (864, false), (960, false), (1056, false), (1152, true), (1344, false)]);
+        let measures: Vec<usize> = tempo_map.measures(0, 2000).map(|beat| beat.tick).collect();
+        assert_eq!(measures, [0, 576, 1152, 1920]);
+    }
+}
@@ -0,0 +1,369 @@
+use std::{cmp::Reverse, collections::HashMap, slice};
+
+use super::{drums, parse_chart, parse_events, parse_song, parse_sync, parse_unknown, Chart, Context, GlobalEvent, LocalEvent, RawSection, SectionId, Song, SongSection, SyncEvent};
+
+/// Serializes a `Song` back into the `.chart` format
+/// Songs parsed from a `.chart` keep the text they had, only the lines for things that changed since then get written again
+/// Charts with an `Unknown` instrument are skipped since we don't know what section they came from
+pub fn write(song: &Song) -> String {
+    let cr = if song.layout.as_ref().is_some_and(|layout| layout.crlf) { "\r" } else { "" };
+
+    let mut lines = Vec::new();
+    let mut written = Vec::new();
+    if let Some(layout) = &song.layout {
+        lines.extend(layout.prefix.iter().cloned());
+        for (i, raw) in layout.sections.iter().enumerate() {
+            // only the last copy of a duplicated section made it into the song, the others go back untouched
+            let body = if layout.sections[i + 1..].iter().any(|later| later.id == raw.id) {
+                raw.lines.clone()
+            } else {
+                written.push(raw.id);
+                let Some(current) = section_lines(song, raw.id) else {
+                    // it was taken out of the song, but whatever came after the `}` stays
+                    lines.extend(raw.footer.iter().filter(|line| line.trim() != "}").cloned());
+                    continue;
+                };
+                merge(raw, &current, cr)
+            };
+            lines.extend(raw.header.iter().cloned());
+            lines.extend(body);
+            lines.extend(raw.footer.iter().cloned());
+        }
+    }
+
+    // a line break at the end of the file leaves an empty last line, new sections go before it
+    let newline_at_end = song.layout.is_none() || lines.last().is_some_and(|line| line.is_empty());
+    if lines.last().is_some_and(|line| line.is_empty()) { lines.pop(); }
+
+    // sections that weren't in the file (so all of them for midi) go at the end in the usual order
+    for id in default_order(song).into_iter().filter(|id| !written.contains(id)) {
+        let (Some(name), Some(current)) = (section_name(song, id), section_lines(song, id)) else { continue };
+        lines.push(format!("[{name}]{cr}"));
+        lines.push(format!("{{{cr}"));
+        lines.extend(current.into_iter().map(|line| format!("  {line}{cr}")));
+        lines.push(format!("}}{cr}"));
+    }
+    if newline_at_end { lines.push(String::new()); }
+
+    let bom = if song.layout.as_ref().is_some_and(|layout| layout.bom) { "\u{FEFF}" } else { "" };
+    format!("{bom}{}", lines.join("\n"))
+}
+
+/// Song, SyncTrack and Events, then the charts from expert to easy for each instrument, then anything we don't understand
+fn default_order(song: &Song) -> Vec<SectionId> {
+    let mut charts: Vec<_> = song.charts.keys().collect();
+    charts.sort_by_key(|(instrument, difficulty)| (*instrument, Reverse(*difficulty)));
+
+    let mut res = vec![SectionId::Song, SectionId::SyncTrack, SectionId::Events];
+    res.extend(charts.into_iter().map(|(instrument, difficulty)| SectionId::Chart(*instrument, *difficulty)));
+    res.extend((0..song.unknown_sections.len()).map(SectionId::Unknown));
+    res
+}
+
+fn section_name(song: &Song, id: SectionId) -> Option<String> {
+    match id {
+        SectionId::Song => Some("Song".into()),
+        SectionId::SyncTrack => Some("SyncTrack".into()),
+        SectionId::Events => Some("Events".into()),
+        SectionId::Chart(instrument, difficulty) => {
+            let Some(instrument_name) = instrument.chart_name() else {
+                println!("can't write chart for unknown instrument ({difficulty:?})");
+                return None;
+            };
+            Some(format!("{}{instrument_name}", difficulty.chart_name()))
+        }
+        SectionId::Unknown(index) => song.unknown_sections.get(index).map(|(name, _)| name.clone()),
+    }
+}
+
+/// The lines a section would have if it was written from scratch, `None` if the song doesn't have it
+fn section_lines(song: &Song, id: SectionId) -> Option<Vec<String>> {
+    match id {
+        SectionId::Song => song.metadata.as_ref().map(write_song),
+        SectionId::SyncTrack => song.sync_track.as_ref().map(|sync_track| write_sync(sync_track, &song.unknown_sync)),
+        SectionId::Events => song.events.as_ref().map(|events| write_events(events, &song.unknown_events)),
+        // generated charts weren't in the original file
+        SectionId::Chart(instrument, difficulty) => song.charts.get(&(instrument, difficulty)).filter(|chart| !chart.is_generated).map(write_chart),
+        SectionId::Unknown(index) => song.unknown_sections.get(index).map(|(_, lines)| lines.clone()),
+    }
+}
+
+/// Keeps the original text of every line that's still in `current`, and puts in new lines for everything else
+fn merge(raw: &RawSection, current: &[String], cr: &str) -> Vec<String> {
+    // nothing changed, which is most of the time
+    if normalize(raw.id, &raw.lines) == current {
+        return raw.lines.clone();
+    }
+
+    let mut remaining: HashMap<&str, usize> = HashMap::new();
+    for line in current {
+        *remaining.entry(line).or_default() += 1;
+    }
+
+    // `(tick, line)`, the tick is `None` for lines that aren't on one
+    let mut res: Vec<(Option<usize>, String)> = Vec::new();
+    for line in &raw.lines {
+        let normalized = normalize(raw.id, slice::from_ref(line));
+        if normalized.is_empty() {
+            // blank or malformed lines never made it into the song, so they can't have changed
+            res.push((None, line.clone()));
+        } else if normalized.iter().all(|line| remaining.get(line.as_str()).is_some_and(|count| *count > 0)) {
+            for line in &normalized {
+                *remaining.get_mut(line.as_str()).unwrap() -= 1;
+            }
+            res.push((line_tick(&normalized[0]), line.clone()));
+        }
+        // anything else was changed or removed
+    }
+
+    // new lines go after the last line on the same tick or before it, or at the end if they don't have one
+    for line in current {
+        let Some(count) = remaining.get_mut(line.as_str()).filter(|count| **count > 0) else { continue };
+        *count -= 1;
+
+        let tick = line_tick(line);
+        let index = match tick {
+            Some(tick) => res.iter().rposition(|(line_tick, _)| line_tick.is_some_and(|line_tick| line_tick <= tick)).map_or(0, |i| i + 1),
+            None => res.len(),
+        };
+        res.insert(index, (tick, format!("  {line}{cr}")));
+    }
+
+    res.into_iter().map(|(_, line)| line).collect()
+}
+
+/// Parses lines from a section and writes them again, so they can be compared with what's in the song
+fn normalize(id: SectionId, lines: &[String]) -> Vec<String> {
+    let lines: Vec<String> = lines.iter().cloned().chain(["}".to_string()]).collect();
+    let mut ctx = Context { file: "", lines: &lines, section: String::new(), lenient: true, warnings: Vec::new() };
+    let mut i = 0;
+    let mut unknown = Vec::new();
+
+    // lenient parsing doesn't fail, malformed lines just get left out
+    let res = match id {
+        SectionId::Song => parse_song(&mut ctx, &mut i).map(|metadata| write_song(&metadata)),
+        SectionId::SyncTrack => parse_sync(&mut ctx, &mut i, &mut unknown).map(|sync_track| write_sync(&sync_track, &unknown)),
+        SectionId::Events => parse_events(&mut ctx, &mut i, &mut unknown).map(|events| write_events(&events, &unknown)),
+        SectionId::Chart(instrument, _) => parse_chart(&mut ctx, &mut i, instrument).map(|chart| write_chart(&chart)),
+        SectionId::Unknown(_) => parse_unknown(&mut ctx, &mut i),
+    };
+    res.unwrap_or_default()
+}
+
+fn line_tick(line: &str) -> Option<usize> {
+    line.split_once(" = ")?.0.parse().ok()
+}
+
+fn write_song(metadata: &SongSection) -> Vec<String> {
+    let mut res = Vec::new();
+
+    let strings = [
+        ("Name", &metadata.name),
+        ("Artist", &metadata.artist),
+        ("Charter", &metadata.charter),
+        ("Album", &metadata.album),
+    ];
+    for (key, value) in strings {
+        if let Some(value) = value { res.push(format!("{key} = \"{value}\"")); }
+    }
+
+    // the year has a weird leading comma for some reason, `parse_song` strips it
+    if let Some(year) = &metadata.year { res.push(format!("Year = \", {year}\"")); }
+    if let Some(offset) = metadata.offset { res.push(format!("Offset = {offset}")); }
+    if let Some(resolution) = metadata.resolution { res.push(format!("Resolution = {resolution}")); }
+    if let Some(difficulty) = metadata.difficulty { res.push(format!("Difficulty = {difficulty}")); }
+    if let Some(preview_start) = metadata.preview_start { res.push(format!("PreviewStart = {preview_start}")); }
+    if let Some(preview_end) = metadata.preview_end { res.push(format!("PreviewEnd = {preview_end}")); }
+    if let Some(genre) = &metadata.genre { res.push(format!("Genre = \"{genre}\"")); }
+    if let Some(length) = metadata.length { res.push(format!("Length = {length}")); }
+
+    for (key, value) in &metadata.unknown {
+        res.push(format!("{key} = {value}"));
+    }
+
+    res
+}
+
+pub fn write_sync(sync_track: &[(usize, SyncEvent)], unknown: &[(usize, String)]) -> Vec<String> {
+    let lines = sync_track.iter().map(|(tick, event)| (*tick, match event {
+        // the denominator is stored as a power of 2 and can be left out if it's 4
+        SyncEvent::TimeSignature { num, den: 4 } => format!("TS {num}"),
+        SyncEvent::TimeSignature { num, den } => format!("TS {num} {}", den.ilog2()),
+        SyncEvent::Tempo(tempo) => format!("B {}", (tempo.bpm * 1000.0).round() as usize),
+        SyncEvent::Anchor { micros } => format!("A {micros}"),
+    })).collect();
+    sorted_lines(lines, unknown)
+}
+
+pub fn write_events(events: &[(usize, GlobalEvent)], unknown: &[(usize, String)]) -> Vec<String> {
+    let lines = events.iter().map(|(tick, event)| {
+        let event = match event {
+            GlobalEvent::Section(name) => format!("section {name}"),
+            GlobalEvent::PhraseStart => "phrase_start".into(),
+            GlobalEvent::Lyric(lyric) => format!("lyric {lyric}"),
+            GlobalEvent::PhraseEnd => "phrase_end".into(),
+            GlobalEvent::SongEnd => "end".into(),
+        };
+        (*tick, format!("E \"{event}\""))
+    }).collect();
+    sorted_lines(lines, unknown)
+}
+
+pub fn write_chart(chart: &Chart) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for note in &chart.notes {
+        for fret in 0..9 {
+            if note.frets >> fret & 1 == 1 {
+                lines.push((note.tick, format!("N {fret} {}", note.length[fret])));
+            }
+        }
+    }
+    for note in &chart.drum_notes {
+        for lane in drums::KICK..=drums::GREEN {
+            if note.lanes >> lane & 1 == 0 { continue; }
+
+            // 2x kicks replace the normal kick instead of being a flag on it
+            let note_type = if lane == drums::KICK && note.double_kick { drums::DOUBLE_KICK } else { lane };
+            lines.push((note.tick, format!("N {note_type} {}", note.length[lane as usize])));
+        }
+        for lane in drums::KICK..=drums::GREEN {
+            if note.is_accent(lane) { lines.push((note.tick, format!("N {} 0", drums::ACCENT_START + lane))); }
+            if note.is_ghost(lane) { lines.push((note.tick, format!("N {} 0", drums::GHOST_START + lane))); }
+            if note.is_cymbal(lane) { lines.push((note.tick, format!("N {} 0", drums::CYMBAL_START + lane))); }
+        }
+    }
+    for starpower in &chart.starpower_events {
+        lines.push((starpower.tick, format!("S 2 {}", starpower.length)));
+    }
+    for fill in &chart.drum_fills {
+        lines.push((fill.tick, format!("S 64 {}", fill.length)));
+    }
+    for (tick, _, event) in &chart.local_events {
+        lines.push((*tick, match event {
+            LocalEvent::SoloStart => "E solo".into(),
+            LocalEvent::SoloEnd => "E soloend".into(),
+        }));
+    }
+
+    sorted_lines(lines, &chart.unknown_events)
+}
+
+/// Adds the lines we didn't understand back in and sorts everything by tick
+fn sorted_lines(mut lines: Vec<(usize, String)>, unknown: &[(usize, String)]) -> Vec<String> {
+    lines.extend_from_slice(unknown);
+    // sorting is stable, so everything on the same tick stays in the order it was added
+    lines.sort_by_key(|line| line.0);
+    lines.into_iter().map(|(tick, line)| format!("{tick} = {line}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write;
+    use crate::chart::{parse, parse_bytes, Difficulty, GlobalEvent, HopoSettings, Instrument, Song, SongSection};
+
+    fn parse_str(text: &str) -> Song {
+        parse_bytes("test.chart".into(), text.as_bytes().to_vec(), false, HopoSettings::default()).unwrap().0
+    }
+
+    fn parse_file(path: &str) -> (Song, String) {
+        let file = format!("{}/{path}", env!("CARGO_MANIFEST_DIR"));
+        let text = String::from_utf8(std::fs::read(&file).unwrap()).unwrap();
+        (parse(file).unwrap(), text)
+    }
+
+    /// Lines that are different between two texts with the same number of lines
+    fn changed_lines<'a>(old: &'a str, new: &'a str) -> Vec<(&'a str, &'a str)> {
+        assert_eq!(old.split('\n').count(), new.split('\n').count());
+        old.split('\n').zip(new.split('\n')).filter(|(old, new)| old != new).collect()
+    }
+
+    #[test]
+    fn round_trip_star() {
+        let (song, text) = parse_file("songs/Star/notes.chart");
+        assert_eq!(write(&song), text);
+    }
+
+    #[test]
+    fn round_trip_open_chords() {
+        let (song, text) = parse_file("songs/openchordtest/notes.chart");
+        assert_eq!(write(&song), text);
+    }
+
+    #[test]
+    fn unknown_sections_and_events_stay_as_they_are() {
+        let chart = "\
+[Song]
+{
+  Name = \"Probe\"
+  Foo = bar
+  Artist = \"Someone\"
+  Offset = 0.000
+  Year = \"2020\"
+  Resolution = 192
+}
+[SyncTrack]
+{
+  0 = B 120000
+}
+[ExpertBanjo]
+{
+  0 = N 0 0
+}
+[Events]
+{
+  0 = E \"crowd_normal\"
+  0 = E \"section intro\"
+  0 = E \"Section chorus\"
+  96 = E \"section_verse_1\"
+  192 = E \"phrase_start\"
+  192 = E \"lyric Hel-\"
+  384 = E \"phrase_end\"
+}
+[ExpertSingle]
+{
+  0 = N 0 0
+}
+[HardBanjo]
+{
+  0 = N 1 0
+}";
+        let song = parse_str(chart);
+
+        assert_eq!(song.charts.values().filter(|chart| !chart.is_generated).count(), 1);
+        assert_eq!(song.unknown_sections.len(), 2);
+        let sections: Vec<_> = song.events.as_ref().unwrap().iter().filter_map(|(_, event)| match event {
+            GlobalEvent::Section(name) => Some(name.as_str()),
+            _ => None,
+        }).collect();
+        assert_eq!(sections, ["intro", "chorus", "verse_1"]);
+        assert_eq!(song.unknown_events, [(0, "E \"crowd_normal\"".into())]);
+        assert_eq!(write(&song), chart);
+    }
+
+    #[test]
+    fn only_changed_lines_are_written_again() {
+        let (mut song, text) = parse_file("songs/Star/notes.chart");
+
+        let chart = song.charts.get_mut(&(Instrument::Single, Difficulty::Expert)).unwrap();
+        let note = &mut chart.notes[10];
+        let fret = note.frets.trailing_zeros() as usize;
+        note.length[fret] += 1;
+        let (tick, length) = (note.tick, note.length[fret]);
+
+        let written = write(&song);
+        let old_line = text.split('\n').find(|line| line.trim() == format!("{tick} = N {fret} {}", length - 1)).unwrap();
+        assert_eq!(changed_lines(&text, &written), [(old_line, format!("  {tick} = N {fret} {length}\r").as_str())]);
+
+        // changed metadata gets written at the end of the section
+        song.metadata = Some(SongSection { name: Some("Not Star".into()), ..song.metadata.clone().unwrap() });
+        let written = write(&song);
+        let song_section: Vec<&str> = written.split('\n').skip_while(|line| !line.starts_with('{')).take_while(|line| !line.starts_with('}')).collect();
+        assert!(!song_section.contains(&"  Name = \"Star\"\r"));
+        assert_eq!(song_section.last(), Some(&"  Name = \"Not Star\"\r"));
+
+        // and sections that aren't in the song anymore are left out
+        song.charts.remove(&(Instrument::Single, Difficulty::Easy));
+        assert!(!write(&song).contains("[EasySingle]"));
+    }
+}
@@ -1,6 +1,8 @@
 use std::{fs, io::Result};
 use serde::{Deserialize, Serialize};
 
+use crate::chart::Instrument;
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(default)]
 pub struct Config {
@@ -10,6 +12,10 @@ pub struct Config {
     pub height: u32,
     pub resizable: bool,
     pub wor_tap: bool,
+    pub instrument: Instrument,
+    pub songs_folder: String,
+    // the folder name or name of the song to play, the first song in the library if it's not set
+    pub song: Option<String>,
 }
 
 impl Default for Config {
@@ -21,6 +27,9 @@ impl Default for Config {
             height: 720,
             resizable: false,
             wor_tap: false,
+            instrument: Instrument::Single,
+            songs_folder: "songs".into(),
+            song: None,
         }
     }
 }
@@ -26,6 +26,8 @@ pub struct InputManager {
     rx: Receiver<InputEvent>,
 
     bot: bool,
+    // every note is tappable, used for keys
+    strumless: bool,
 
     pending_strum: bool,
     strum_time: u128,
@@ -33,7 +35,7 @@ pub struct InputManager {
 }
 
 impl InputManager {
-    pub fn new(bot: bool) -> Self {
+    pub fn new(bot: bool, strumless: bool) -> Self {
         let (thread, rx) = InputThread::spawn();
         Self {
             main_device: None,
@@ -42,6 +44,7 @@ impl InputManager {
             rx,
 
             bot,
+            strumless,
 
             pending_strum: false,
             strum_time: 0,
@@ -115,7 +118,7 @@ impl InputManager {
             let note = note.note;
             let time = sec_to_ns(note.time) - timestamp as i128 - time_offset;
             if time < sec_to_ns(HIT_FRONT) && time > -sec_to_ns(HIT_BACK) {
-                let tappable = note.is_hopo || note.frets >> 6 & 1 == 1;
+                let tappable = self.strumless || note.is_hopo || note.frets >> 6 & 1 == 1;
 
                 // anchoring check
                 let lowest_note = lsb(note.frets_masked);
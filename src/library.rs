@@ -0,0 +1,266 @@
+use std::{collections::HashMap, fs, io::Result, path::{Path, PathBuf}, time::{Instant, UNIX_EPOCH}};
+use serde::{Deserialize, Serialize};
+use symphonia::core::io::MediaSource;
+
+use crate::chart::{self, Difficulty, Instrument, SngFile, SngReader};
+
+const CHART_FILES: [&str; 2] = ["notes.chart", "notes.mid"];
+const AUDIO_EXTENSIONS: [&str; 5] = ["ogg", "opus", "mp3", "wav", "flac"];
+const IMAGE_EXTENSIONS: [&str; 3] = ["png", "jpg", "jpeg"];
+
+/// Where a song's audio is read from
+pub enum AudioSource {
+    File(PathBuf),
+    // streamed out of a `.sng`
+    Sng(SngReader),
+}
+
+// lets kira stream audio straight out of a `.sng`
+impl MediaSource for SngReader {
+    fn is_seekable(&self) -> bool { true }
+    fn byte_len(&self) -> Option<u64> { Some(self.len()) }
+}
+
+/// A song folder or `.sng` found while scanning, with everything needed to list it without parsing the chart again
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SongEntry {
+    pub path: PathBuf,
+    // newest modification time of the folder and the files in it, in milliseconds since the unix epoch
+    pub modified: u64,
+
+    pub chart_file: String,
+    // `Song::hash` of the chart file
+    pub hash: u64,
+    // file names, not full paths, for `.sng`s these are the names inside of it
+    pub audio: Vec<String>,
+    pub images: Vec<String>,
+
+    pub name: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<String>,
+    pub charter: Option<String>,
+    pub length: Option<f64>, // in seconds
+
+    pub charts: Vec<(Instrument, Difficulty)>,
+    // from song.ini, or estimated from the hardest chart if it doesn't have one
+    pub difficulties: Vec<(Instrument, usize)>,
+}
+
+impl SongEntry {
+    pub fn is_sng(&self) -> bool {
+        self.path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("sng"))
+    }
+
+    /// Gets the audio file for a stem (`song`, `guitar`, `drums_1`...)
+    pub fn stem(&self, stem: &str) -> Option<&str> {
+        self.audio.iter()
+            .find(|file| Path::new(file).file_stem().is_some_and(|name| name.eq_ignore_ascii_case(stem)))
+            .map(|file| file.as_str())
+    }
+
+    /// Every audio file that should play during the song, which is everything but the preview
+    pub fn stems(&self) -> Vec<&str> {
+        self.audio.iter()
+            .filter(|file| !Path::new(file).file_stem().is_some_and(|name| name.eq_ignore_ascii_case("preview")))
+            .map(|file| file.as_str())
+            .collect()
+    }
+
+    /// Opens one of the files in `audio`, audio in a `.sng` is streamed out of it and unmasked as it plays
+    pub fn open_audio(&self, file: &str) -> Result<AudioSource> {
+        if self.is_sng() {
+            Ok(AudioSource::Sng(SngFile::open(&self.path)?.open_file(file)?))
+        } else {
+            Ok(AudioSource::File(self.path.join(file)))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Library {
+    pub songs: Vec<SongEntry>,
+}
+
+impl Library {
+    /// Finds a song by its folder (or `.sng` file) name or its name
+    pub fn find(&self, name: &str) -> Option<&SongEntry> {
+        self.songs.iter().find(|song| song.path.file_name().is_some_and(|folder| folder.eq_ignore_ascii_case(name)))
+            .or_else(|| self.songs.iter().find(|song| song.name.eq_ignore_ascii_case(name)))
+    }
+}
+
+/// Recursively scans `folder` for songs, reusing entries from the cache at `cache` if their folder hasn't changed
+/// The updated cache is written back afterwards
+pub fn scan(folder: &str, cache: &str) -> Library {
+    let start = Instant::now();
+
+    let mut cached: HashMap<PathBuf, SongEntry> = load_cache(cache)
+        .songs
+        .into_iter()
+        .map(|song| (song.path.clone(), song))
+        .collect();
+
+    let mut library = Library::default();
+    let mut parsed = 0;
+    scan_folder(Path::new(folder), &mut cached, &mut library, &mut parsed);
+    library.songs.sort_by_key(|song| song.name.to_lowercase());
+
+    println!("scanning {} songs took {}ms ({parsed} parsed)", library.songs.len(), start.elapsed().as_millis());
+
+    if let Err(e) = save_cache(cache, &library) {
+        println!("failed to write song cache: {e}");
+    }
+
+    library
+}
+
+fn scan_folder(folder: &Path, cached: &mut HashMap<PathBuf, SongEntry>, library: &mut Library, parsed: &mut usize) {
+    let Ok(read_dir) = fs::read_dir(folder) else {
+        println!("can't read folder {}", folder.display());
+        return;
+    };
+
+    let mut files = Vec::new();
+    let mut folders = Vec::new();
+    for entry in read_dir.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            folders.push(path);
+        } else if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+            files.push(name.to_string());
+        }
+    }
+
+    // `.sng`s can be next to anything
+    for file in &files {
+        let path = folder.join(file);
+        if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("sng")) {
+            let modified = modified_time(&path, &[]);
+            let entry = match cached.remove(&path) {
+                Some(entry) if entry.modified == modified => Some(entry),
+                _ => {
+                    *parsed += 1;
+                    read_sng(&path, modified)
+                }
+            };
+            library.songs.extend(entry);
+        }
+    }
+
+    // a song folder can't have songs inside of it
+    if let Some(chart_file) = CHART_FILES.iter().find(|chart| files.iter().any(|file| file.eq_ignore_ascii_case(chart))) {
+        let modified = modified_time(folder, &files);
+        let entry = match cached.remove(folder) {
+            Some(entry) if entry.modified == modified => Some(entry),
+            _ => {
+                *parsed += 1;
+                read_song(folder, chart_file, modified, &files)
+            }
+        };
+        library.songs.extend(entry);
+        return;
+    }
+
+    folders.sort();
+    for folder in folders {
+        scan_folder(&folder, cached, library, parsed);
+    }
+}
+
+fn read_sng(file: &Path, modified: u64) -> Option<SongEntry> {
+    let sng = match SngFile::open(file) {
+        Ok(sng) => sng,
+        Err(e) => {
+            println!("failed to open {}: {e}", file.display());
+            return None;
+        }
+    };
+    let Some(chart_file) = CHART_FILES.iter().find(|chart| sng.contains(chart)) else {
+        println!("{} doesn't have a chart", file.display());
+        return None;
+    };
+
+    let files: Vec<String> = sng.files.iter().map(|file| file.name.clone()).collect();
+    read_song(file, chart_file, modified, &files)
+}
+
+fn read_song(folder: &Path, chart_file: &str, modified: u64, files: &[String]) -> Option<SongEntry> {
+    let song = match chart::load(&folder.to_string_lossy()) {
+        Ok(song) => song,
+        Err(e) => {
+            println!("failed to load {}: {e}", folder.display());
+            return None;
+        }
+    };
+    let info = song.info();
+
+    let with_extension = |extensions: &[&str]| -> Vec<String> {
+        let mut matching: Vec<String> = files.iter()
+            .filter(|file| Path::new(file).extension().and_then(|ext| ext.to_str()).is_some_and(|ext| extensions.contains(&ext.to_lowercase().as_str())))
+            .cloned()
+            .collect();
+        matching.sort();
+        matching
+    };
+
+    let mut charts: Vec<_> = song.charts.keys().copied().collect();
+    charts.sort();
+
+    let mut difficulties: Vec<(Instrument, usize)> = Vec::new();
+    // `charts` is sorted, so the last chart for each instrument is the hardest
+    for (instrument, difficulty) in charts.iter().rev() {
+        if difficulties.iter().any(|(existing, _)| existing == instrument) { continue; }
+        let value = info.instrument_difficulties.get(instrument).copied()
+            .or_else(|| song.analyze(*instrument, *difficulty).map(|stats| stats.difficulty.round() as usize));
+        if let Some(value) = value {
+            difficulties.push((*instrument, value));
+        }
+    }
+    difficulties.reverse();
+
+    Some(SongEntry {
+        path: folder.to_path_buf(),
+        modified,
+
+        chart_file: chart_file.into(),
+        hash: song.hash,
+        audio: with_extension(&AUDIO_EXTENSIONS),
+        images: with_extension(&IMAGE_EXTENSIONS),
+
+        name: info.name.unwrap_or_else(|| folder.file_stem().unwrap_or_default().to_string_lossy().into_owned()),
+        artist: info.artist,
+        album: info.album,
+        genre: info.genre,
+        year: info.year,
+        charter: info.charter,
+        length: info.length,
+
+        charts,
+        difficulties,
+    })
+}
+
+// editing a file in place doesn't always touch the folder's mtime, so check the files too
+fn modified_time(folder: &Path, files: &[String]) -> u64 {
+    std::iter::once(folder.to_path_buf())
+        .chain(files.iter().map(|file| folder.join(file)))
+        .filter_map(|path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+        .filter_map(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|time| time.as_millis() as u64)
+        .max()
+        .unwrap_or(0)
+}
+
+fn load_cache(path: &str) -> Library {
+    let Ok(json) = fs::read_to_string(path) else { return Library::default() };
+    serde_json::from_str(&json).unwrap_or_else(|e| {
+        println!("song cache is invalid, rescanning everything: {e}");
+        Library::default()
+    })
+}
+
+fn save_cache(path: &str, library: &Library) -> Result<()> {
+    fs::write(path, serde_json::to_string(library).unwrap())
+}
@@ -1,6 +1,7 @@
 mod chart;
 mod config;
 mod input;
+mod library;
 mod render;
 
 use std::{collections::VecDeque, sync::OnceLock, time::{Duration, Instant}};
@@ -8,7 +9,7 @@ use std::{collections::VecDeque, sync::OnceLock, time::{Duration, Instant}};
 use kira::{sound::streaming::StreamingSoundData, AudioManager, AudioManagerSettings, Tween};
 use macroquad::prelude::*;
 
-use crate::{chart::{Difficulty, Instrument, Note}, config::{Config, load_config}, input::InputManager, render::*};
+use crate::{chart::{Difficulty, Note}, config::{Config, load_config}, input::InputManager, library::AudioSource, render::*};
 
 // haha it says fart
 const FAR_T: f32 = 0.0;
@@ -78,8 +79,37 @@ fn window_conf() -> Conf {
     }
 }
 
-#[macroquad::main(window_conf)]
-async fn main() {
+fn main() {
+    // `open_hero lint <song>` checks a song for charting mistakes instead of playing it
+    let args: Vec<String> = std::env::args().collect();
+    if let [_, command, song] = &args[..] && command == "lint" {
+        lint(song);
+        return;
+    }
+
+    macroquad::Window::from_config(window_conf(), game());
+}
+
+fn lint(path: &str) {
+    let song = match chart::load(path) {
+        Ok(song) => song,
+        Err(err) => {
+            println!("failed to load {path}: {err}");
+            std::process::exit(1);
+        }
+    };
+
+    let warnings = song.lint();
+    for warning in &warnings {
+        println!("warning: {warning}");
+    }
+    println!("{} warnings", warnings.len());
+    if !warnings.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+async fn game() {
     let start = Instant::now();
     let assets = load_assets("assets").await;
     println!("loading assets took {}ms", start.elapsed().as_millis());
@@ -88,28 +118,52 @@ async fn main() {
 
     let mut strikeline = Strikeline::default();
 
-    let song_name = "Star";
-    let audio_file = "song.ogg";
-    let song = chart::parse(format!("songs/{song_name}/notes.chart")).unwrap();
-    let chart = song.charts.get(&(Instrument::Single, Difficulty::Expert)).unwrap();
+    let library = library::scan(&config.songs_folder, "songs.cache.json");
+    let entry = match &config.song {
+        Some(name) => library.find(name).expect("song not found in the library"),
+        None => library.songs.first().expect("no songs found"),
+    };
+    let start = Instant::now();
+    let song = chart::load(&entry.path.to_string_lossy()).unwrap();
+    println!("loading the chart took {}ms", start.elapsed().as_millis());
+    for warning in &song.load_warnings {
+        println!("warning: {warning}");
+    }
+    println!("playing {} by {}", entry.name, entry.artist.as_deref().unwrap_or("unknown artist"));
+    let chart = song.charts.get(&(config.instrument, Difficulty::Expert)).unwrap();
+    let tempo_map = song.tempo_map.as_ref().unwrap();
     let mut notes: VecDeque<NoteContainer> = chart.notes.iter().map(|note| NoteContainer { note: *note, t: 0.0 }).collect();
 
     let volume = -12.0;
     // let volume = -100000.0;
     let mut manager: AudioManager = AudioManager::new(AudioManagerSettings::default()).unwrap();
-    let audio = StreamingSoundData::from_file(format!("songs/{song_name}/{audio_file}")).unwrap().volume(volume);
 
+    // every stem plays at once
+    let stems = entry.stems();
+    if stems.is_empty() {
+        println!("{} has no audio", entry.name);
+    }
     let mut audio_playing = false;
-    let mut audio_handle = manager.play(audio).unwrap();
-    audio_handle.pause(Tween {
-        duration: Duration::from_millis(1),
-        ..Default::default()
-    });
-    audio_handle.seek_to(0.0);
+    let mut audio_handles = Vec::new();
+    for stem in stems {
+        let audio = match entry.open_audio(stem).unwrap() {
+            AudioSource::File(path) => StreamingSoundData::from_file(path),
+            AudioSource::Sng(reader) => StreamingSoundData::from_media_source(reader),
+        }.unwrap().volume(volume);
+        let mut audio_handle = manager.play(audio).unwrap();
+        audio_handle.pause(Tween {
+            duration: Duration::from_millis(1),
+            ..Default::default()
+        });
+        audio_handle.seek_to(0.0);
+        audio_handles.push(audio_handle);
+    }
 
-    let mut input = InputManager::new(false);
+    let mut input = InputManager::new(false, config.instrument.is_strumless());
 
-    let mut time = -2.5;
+    // give at least 2.5 seconds before the first note, even if the offset puts it before the audio
+    let first_note = notes.front().map_or(0.0, |note| note.note.time);
+    let mut time = (first_note - 2.5).min(-2.5);
     let mut time_offset = 0.0; // offset between input system time and game time
     let mut frame_count = 0;
     loop {
@@ -135,6 +189,13 @@ async fn main() {
             vec2(t_to_x(hit_end,   -0.5), t_to_y(hit_end)),
         ], Color::new(1.0, 1.0, 1.0, 0.25));
 
+        // beat lines
+        let beats_start = tempo_map.seconds_to_tick(time + t_to_time(NEAR_T, config.notespeed)).max(0.0).ceil() as usize;
+        let beats_end = tempo_map.seconds_to_tick(time + t_to_time(FAR_T, config.notespeed)).max(0.0).ceil() as usize;
+        for beat in tempo_map.beats(beats_start, beats_end) {
+            render_beat_line(time_to_t(beat.time - time, config.notespeed), beat.is_measure);
+        }
+
         // strikeline
         for i in 0..5 {
             render_fret(&assets, i, strikeline.frets[i], strikeline.pressed >> i & 1 == 1);
@@ -154,6 +215,8 @@ async fn main() {
             render_note(&assets, config, &note.note, note.t);
         }
 
+        render_lyrics(&song.lyrics, time);
+
         draw_fps();
 
         // skip the first couple frames because of large frame times
@@ -167,10 +230,12 @@ async fn main() {
 
         // start the song when time >= 0
         if time >= 0.0 && !audio_playing {
-            audio_handle.resume(Tween {
-                duration: Duration::from_millis(1),
-                ..Default::default()
-            });
+            for audio_handle in &mut audio_handles {
+                audio_handle.resume(Tween {
+                    duration: Duration::from_millis(1),
+                    ..Default::default()
+                });
+            }
             audio_playing = true;
         }
 
@@ -3,7 +3,7 @@
 
 use macroquad::prelude::*;
 
-use crate::{config::Config, chart::Note, Assets, FretState, FADE_T, FAR_T};
+use crate::{config::Config, chart::{LyricPhrase, Note}, Assets, FretState, FADE_T, FAR_T};
 
 pub fn render_fret(assets: &Assets, fret: usize, state: FretState, pressed: bool) {
     let textures = if fret == 0 || fret == 4 {
@@ -45,6 +45,9 @@ pub fn render_texture(texture: &Texture2D, x: f32, y: f32, scale: f32, flip_x: b
 /// `notespeed` is in CH notespeed
 pub fn time_to_t(time: f64, notespeed: f32) -> f32 { (1.0 - (time * notespeed as f64) / 7.87) as f32 }
 
+/// The inverse of `time_to_t`
+pub fn t_to_time(t: f32, notespeed: f32) -> f64 { (1.0 - t as f64) * 7.87 / notespeed as f64 }
+
 pub fn render_note(assets: &Assets, config: &Config, note: &Note, t: f32) {
     if note.frets >> 7 & 1 == 1 {
         if note.is_hopo || note.frets >> 6 & 1 == 1 {
@@ -116,6 +119,52 @@ pub fn render_gem(texture: &Texture2D, fret: usize, t: f32) {
     );
 }
 
+pub fn render_beat_line(t: f32, is_measure: bool) {
+    let alpha = ((t - FAR_T) / FADE_T).min(1.0);
+    let t = perspective(t);
+    let (thickness, brightness) = if is_measure { (3.0, 0.5) } else { (1.0, 0.25) };
+    draw_line(
+        t_to_x(t, -0.5),
+        t_to_y(t),
+        t_to_x(t, 4.5),
+        t_to_y(t),
+        thickness * get_scale(),
+        Color::new(1.0, 1.0, 1.0, brightness * alpha)
+    );
+}
+
+/// Draws the current lyric phrase karaoke style with the next one under it
+pub fn render_lyrics(phrases: &[LyricPhrase], time: f64) {
+    let current = phrases.partition_point(|phrase| phrase.end_time < time);
+    let scale = get_scale();
+
+    for (line, phrase) in phrases[current..].iter().take(2).enumerate() {
+        let font_size = if line == 0 { 32.0 } else { 24.0 } * scale;
+        let y = (40.0 + 36.0 * line as f32) * scale;
+
+        let text = phrase.text();
+        let width = measure_text(&text, None, font_size as u16, 1.0).width;
+        let mut x = (screen_width() - width) / 2.0;
+
+        // syllables that have been sung get highlighted, but only in the phrase that's being sung
+        let sung = if line == 0 { phrase.sung(time) } else { 0 };
+        for (i, syllable) in phrase.syllables.iter().enumerate() {
+            let mut text = syllable.text.clone();
+            if !syllable.joins_next { text.push(' '); }
+
+            let color = if i < sung {
+                Color::new(0.3, 0.8, 1.0, 1.0)
+            } else if line == 0 {
+                WHITE
+            } else {
+                GRAY
+            };
+            draw_text(&text, x, y, font_size, color);
+            x += measure_text(&text, None, font_size as u16, 1.0).width;
+        }
+    }
+}
+
 // TODO: textured polygon rendering
 //       this will require writing a custom shader
 